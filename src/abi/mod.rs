@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use starknet::core::types::Felt;
+use starknet::core::utils::starknet_keccak;
+
+use crate::utils::conversions::{
+    decode_array, decode_bool, decode_byte_array, decode_felt, decode_signed, decode_u256,
+    decode_unsigned, decode_variant_index,
+};
+
+/// An event decoded with the ABI of the contract that emitted it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecodedEvent {
+    /// Full name of the event struct, e.g.
+    /// `openzeppelin::token::erc20::erc20::ERC20Component::Transfer`.
+    pub name: String,
+    /// Members of the event by name. Felts and addresses are hex strings,
+    /// integers up to 64 bits are numbers and larger ones decimal strings,
+    /// structs are objects and enums `{"Variant": value}`.
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MemberKind {
+    Key,
+    Data,
+    /// Enum variant whose selector comes before the inner event's keys.
+    Nested,
+    /// Enum variant that reuses the inner event's selector.
+    Flat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Member {
+    name: String,
+    ty: String,
+    kind: MemberKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum EventDef {
+    Struct(Vec<Member>),
+    Enum(Vec<Member>),
+}
+
+/// Types and events of a Cairo contract ABI.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContractAbi {
+    structs: HashMap<String, Vec<Member>>,
+    enums: HashMap<String, Vec<Member>>,
+    /// Ordered by name so events are always matched in the same order.
+    events: BTreeMap<String, EventDef>,
+    /// Event enums no other event refers to, i.e. the contract `Event` enum,
+    /// which comes first.
+    roots: Vec<String>,
+}
+
+impl ContractAbi {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ABI {}", path.display()))?;
+        let json = serde_json::from_str::<Value>(&content)
+            .with_context(|| format!("ABI {} is not valid JSON", path.display()))?;
+
+        Self::from_json(&json).with_context(|| format!("Invalid ABI {}", path.display()))
+    }
+
+    /// Reads a bare ABI array, or a contract class whose `abi` is an array or
+    /// a JSON encoded string as returned by `starknet_getClass`.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let entries = match json {
+            Value::Array(entries) => entries.clone(),
+            Value::Object(class) => match class.get("abi") {
+                Some(Value::Array(entries)) => entries.clone(),
+                Some(Value::String(abi)) => serde_json::from_str(abi)?,
+                _ => anyhow::bail!("the contract class has no `abi`"),
+            },
+            _ => anyhow::bail!("expected an ABI array or a contract class"),
+        };
+
+        let mut abi = ContractAbi::default();
+        for entry in &entries {
+            abi.add_entry(entry)?;
+        }
+
+        let referenced = abi
+            .events
+            .values()
+            .flat_map(|def| match def {
+                EventDef::Struct(members) | EventDef::Enum(members) => members,
+            })
+            .map(|member| member.ty.as_str())
+            .collect::<HashSet<_>>();
+        abi.roots = abi
+            .events
+            .iter()
+            .filter(|(name, def)| matches!(def, EventDef::Enum(_)) && !referenced.contains(name.as_str()))
+            .map(|(name, _)| name.clone())
+            .collect();
+        abi.roots.sort_by_key(|name| !name.ends_with("::Event"));
+
+        Ok(abi)
+    }
+
+    fn add_entry(&mut self, entry: &Value) -> Result<()> {
+        let name = entry["name"].as_str().unwrap_or_default().to_string();
+
+        match entry["type"].as_str() {
+            Some("struct") => {
+                self.structs.insert(name, members(&entry["members"], MemberKind::Data)?);
+            }
+            Some("enum") => {
+                self.enums.insert(name, members(&entry["variants"], MemberKind::Data)?);
+            }
+            Some("event") => {
+                let def = match entry["kind"].as_str() {
+                    Some("struct") => EventDef::Struct(members(&entry["members"], MemberKind::Data)?),
+                    Some("enum") => EventDef::Enum(members(&entry["variants"], MemberKind::Nested)?),
+                    // Cairo 0 events list their keys and data separately
+                    _ => {
+                        let mut fields = members(&entry["keys"], MemberKind::Key)?;
+                        fields.extend(members(&entry["data"], MemberKind::Data)?);
+                        EventDef::Struct(fields)
+                    }
+                };
+                self.events.insert(name, def);
+            }
+            // Functions, interfaces, impls and constructors don't matter here
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Decodes an event from its keys, selector first, and data.
+    pub fn decode_event(&self, keys: &[Felt], data: &[Felt]) -> Result<DecodedEvent> {
+        let selector = *keys.first().context("event without a selector")?;
+
+        let (name, used_keys) = self
+            .roots
+            .iter()
+            .find_map(|root| self.resolve(root, keys))
+            // Cairo 0 events, or ABIs without the contract event enum
+            .or_else(|| {
+                self.events
+                    .iter()
+                    .filter(|_| self.roots.is_empty())
+                    .filter(|(_, def)| matches!(def, EventDef::Struct(_)))
+                    .find(|(name, _)| short_name(name) == selector)
+                    .map(|(name, _)| (name.clone(), 1))
+            })
+            .with_context(|| format!("no event of the ABI has selector {}", selector.to_hex_string()))?;
+
+        let Some(EventDef::Struct(members)) = self.events.get(&name) else {
+            anyhow::bail!("event {} is not a struct", name);
+        };
+
+        let mut keys = keys[used_keys..].iter().copied();
+        let mut data = data.iter().copied();
+        let mut fields = Map::new();
+        for member in members {
+            let value = match member.kind {
+                MemberKind::Key => self.decode_value(&member.ty, &mut keys),
+                _ => self.decode_value(&member.ty, &mut data),
+            }
+            .with_context(|| format!("{}.{}", name, member.name))?;
+            fields.insert(member.name.clone(), value);
+        }
+        // Leftovers mean the event doesn't match the ABI, e.g. an older
+        // version of the contract
+        let (keys_left, data_left) = (keys.count(), data.count());
+        if keys_left != 0 || data_left != 0 {
+            anyhow::bail!(
+                "{} has {} keys and {} data felts left after its last member",
+                name,
+                keys_left,
+                data_left
+            );
+        }
+
+        Ok(DecodedEvent { name, fields })
+    }
+
+    /// Finds the event struct `keys` point to from the event `ty`, with the
+    /// number of keys used as selectors on the way.
+    fn resolve(&self, ty: &str, keys: &[Felt]) -> Option<(String, usize)> {
+        match self.events.get(ty)? {
+            EventDef::Struct(_) => Some((ty.to_string(), 0)),
+            EventDef::Enum(variants) => {
+                let selector = keys.first()?;
+                variants.iter().find_map(|variant| match variant.kind {
+                    MemberKind::Flat => self.resolve(&variant.ty, keys),
+                    _ if starknet_keccak(variant.name.as_bytes()) == *selector => self
+                        .resolve(&variant.ty, &keys[1..])
+                        .map(|(name, used)| (name, used + 1)),
+                    _ => None,
+                })
+            }
+        }
+    }
+
+    /// Decodes a value of the Cairo type `ty` from `felts`.
+    fn decode_value(&self, ty: &str, felts: &mut dyn Iterator<Item = Felt>) -> Result<Value> {
+        let ty = ty.trim();
+
+        if let Some(inner) = generic_arg(ty, "core::array::Array").or_else(|| generic_arg(ty, "core::array::Span")) {
+            return decode_array(felts, |felts| self.decode_value(inner, felts)).map(Value::Array);
+        }
+        if let Some(elements) = ty.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            let elements = split_top_level(elements);
+            if elements.is_empty() {
+                return Ok(Value::Null);
+            }
+            return elements
+                .into_iter()
+                .map(|element| self.decode_value(element, felts))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array);
+        }
+
+        Ok(match ty {
+            "felt" | "core::felt252"
+            | "core::starknet::contract_address::ContractAddress"
+            | "core::starknet::class_hash::ClassHash"
+            | "core::starknet::eth_address::EthAddress"
+            | "core::starknet::storage_access::StorageAddress" => Value::String(decode_felt(felts)?.to_hex_string()),
+            "core::bool" => Value::Bool(decode_bool(felts)?),
+            "core::integer::u8" => Value::from(decode_unsigned::<u8, _>(felts)?),
+            "core::integer::u16" => Value::from(decode_unsigned::<u16, _>(felts)?),
+            "core::integer::u32" => Value::from(decode_unsigned::<u32, _>(felts)?),
+            "core::integer::u64" => Value::from(decode_unsigned::<u64, _>(felts)?),
+            "core::integer::u128" => Value::String(decode_unsigned::<u128, _>(felts)?.to_string()),
+            "core::integer::u256" => {
+                let value = decode_u256(felts)?;
+                let value = (BigUint::from(value.high()) << 128) | BigUint::from(value.low());
+                Value::String(value.to_string())
+            }
+            "core::integer::i8" => Value::from(decode_signed::<i8, _>(felts)?),
+            "core::integer::i16" => Value::from(decode_signed::<i16, _>(felts)?),
+            "core::integer::i32" => Value::from(decode_signed::<i32, _>(felts)?),
+            "core::integer::i64" => Value::from(decode_signed::<i64, _>(felts)?),
+            "core::integer::i128" => Value::String(decode_signed::<i128, _>(felts)?.to_string()),
+            "core::byte_array::ByteArray" => Value::String(decode_byte_array(felts)?),
+            _ => {
+                if let Some(members) = self.structs.get(ty) {
+                    let mut object = Map::new();
+                    for member in members {
+                        object.insert(member.name.clone(), self.decode_value(&member.ty, felts)?);
+                    }
+                    Value::Object(object)
+                } else if let Some(variants) = self.enums.get(ty) {
+                    let variant = &variants[decode_variant_index(felts, variants.len())?];
+                    let mut object = Map::new();
+                    object.insert(variant.name.clone(), self.decode_value(&variant.ty, felts)?);
+                    Value::Object(object)
+                } else {
+                    anyhow::bail!("unknown type {}", ty);
+                }
+            }
+        })
+    }
+}
+
+fn members(value: &Value, default_kind: MemberKind) -> Result<Vec<Member>> {
+    let Some(items) = value.as_array() else {
+        return Ok(Vec::new());
+    };
+
+    items
+        .iter()
+        .map(|item| {
+            let kind = match item["kind"].as_str() {
+                Some("key") => MemberKind::Key,
+                Some("data") => MemberKind::Data,
+                Some("nested") => MemberKind::Nested,
+                Some("flat") => MemberKind::Flat,
+                _ => default_kind,
+            };
+            Ok(Member {
+                name: item["name"].as_str().context("ABI member without a name")?.to_string(),
+                ty: item["type"].as_str().context("ABI member without a type")?.to_string(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// Selector of an event named after the last path segment of `name`.
+fn short_name(name: &str) -> Felt {
+    starknet_keccak(name.rsplit("::").next().unwrap_or(name).as_bytes())
+}
+
+/// `T` in `base::<T>`.
+fn generic_arg<'a>(ty: &'a str, base: &str) -> Option<&'a str> {
+    ty.strip_prefix(base)?.strip_prefix("::<")?.strip_suffix('>')
+}
+
+/// Splits tuple elements on the commas that are not inside a nested type.
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut elements = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                elements.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(list[start..].trim());
+    elements.retain(|e| !e.is_empty());
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use starknet::core::utils::get_selector_from_name;
+
+    /// An OpenZeppelin ERC20 with the Ownable component, its ERC20 events
+    /// flattened into the contract `Event` enum and the Ownable ones nested.
+    const ERC20_OWNABLE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/erc20_ownable.contract_class.json"
+    );
+
+    fn token() -> ContractAbi {
+        ContractAbi::load(Path::new(ERC20_OWNABLE)).unwrap()
+    }
+
+    fn selector(name: &str) -> Felt {
+        get_selector_from_name(name).unwrap()
+    }
+
+    #[test]
+    fn flat_component_events_use_their_own_selector() {
+        let keys = [selector("Transfer"), Felt::from(0x1u64), Felt::from(0x2u64)];
+        let data = [Felt::from(100u64), Felt::ZERO];
+
+        let event = token().decode_event(&keys, &data).unwrap();
+        assert_eq!(event.name, "openzeppelin::token::erc20::erc20::ERC20Component::Transfer");
+        assert_eq!(
+            Value::Object(event.fields),
+            json!({ "from": "0x1", "to": "0x2", "value": "100" })
+        );
+    }
+
+    #[test]
+    fn nested_component_events_follow_the_variant_selector() {
+        let keys = [
+            selector("OwnableEvent"),
+            selector("OwnershipTransferred"),
+            Felt::from(0x1u64),
+            Felt::from(0x2u64),
+        ];
+
+        let event = token().decode_event(&keys, &[]).unwrap();
+        assert_eq!(
+            event.name,
+            "openzeppelin::access::ownable::ownable::OwnableComponent::OwnershipTransferred"
+        );
+        assert_eq!(
+            Value::Object(event.fields),
+            json!({ "previous_owner": "0x1", "new_owner": "0x2" })
+        );
+
+        // The component selector alone is not enough
+        let keys = [selector("OwnershipTransferred"), Felt::from(0x1u64), Felt::from(0x2u64)];
+        assert!(token().decode_event(&keys, &[]).is_err());
+    }
+
+    #[test]
+    fn contract_struct_events_are_decoded() {
+        let keys = [selector("Minted"), Felt::from(0x3u64)];
+        let data = [Felt::from(5u64), Felt::ONE, Felt::from(0x6d656d6fu64)];
+
+        let event = token().decode_event(&keys, &data).unwrap();
+        assert_eq!(event.name, "kanshi_token::token::Token::Minted");
+        assert_eq!(
+            Value::Object(event.fields),
+            json!({
+                "to": "0x3",
+                "amount": "340282366920938463463374607431768211461",
+                "memo": "0x6d656d6f",
+            })
+        );
+    }
+
+    #[test]
+    fn cairo_0_events_list_keys_and_data() {
+        let abi = ContractAbi::from_json(&json!([
+            {
+                "type": "event",
+                "name": "Deposit",
+                "keys": [{ "name": "user", "type": "felt" }],
+                "data": [{ "name": "amount", "type": "felt" }, { "name": "shares", "type": "felt" }]
+            }
+        ]))
+        .unwrap();
+
+        let keys = [selector("Deposit"), Felt::from(0xau64)];
+        let data = [Felt::from(0x10u64), Felt::from(0x8u64)];
+        let event = abi.decode_event(&keys, &data).unwrap();
+        assert_eq!(event.name, "Deposit");
+        assert_eq!(
+            Value::Object(event.fields),
+            json!({ "user": "0xa", "amount": "0x10", "shares": "0x8" })
+        );
+    }
+
+    #[test]
+    fn unknown_selectors_are_rejected() {
+        let keys = [selector("Burned"), Felt::from(0x1u64)];
+
+        let error = token().decode_event(&keys, &[]).unwrap_err();
+        assert!(error.to_string().contains("no event of the ABI has selector"), "{}", error);
+        assert!(token().decode_event(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn felts_left_after_the_last_member_are_rejected() {
+        let abi = token();
+        let keys = [selector("Transfer"), Felt::from(0x1u64), Felt::from(0x2u64)];
+        let data = [Felt::from(100u64), Felt::ZERO];
+
+        let mut extra_keys = keys.to_vec();
+        extra_keys.push(Felt::from(0x3u64));
+        let error = abi.decode_event(&extra_keys, &data).unwrap_err();
+        assert!(error.to_string().contains("1 keys and 0 data"), "{}", error);
+
+        let mut extra_data = data.to_vec();
+        extra_data.push(Felt::ONE);
+        assert!(abi.decode_event(&keys, &extra_data).is_err());
+
+        // And so are missing ones
+        assert!(abi.decode_event(&keys, &data[..1]).is_err());
+    }
+
+    #[test]
+    fn the_contract_event_enum_is_tried_first() {
+        assert_eq!(token().roots, vec!["kanshi_token::token::Token::Event".to_string()]);
+
+        // Two unrelated event enums, as in an ABI exported without its
+        // component enums being referenced
+        let abi = ContractAbi::from_json(&json!([
+            { "type": "event", "name": "a::Moved", "kind": "struct", "members": [] },
+            { "type": "event", "name": "a::Other", "kind": "enum", "variants": [
+                { "name": "Moved", "type": "a::Moved", "kind": "nested" }
+            ] },
+            { "type": "event", "name": "b::Event", "kind": "enum", "variants": [
+                { "name": "Moved", "type": "a::Moved", "kind": "nested" }
+            ] }
+        ]))
+        .unwrap();
+        assert_eq!(abi.roots, vec!["b::Event".to_string(), "a::Other".to_string()]);
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// Everything that can go wrong while loading a `Config`.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Invalid command line, including `--help` and `--version` requests.
+    Args(clap::Error),
+    /// The config file could not be read or parsed.
+    File { path: String, reason: String },
+    MissingKey(String),
+    InvalidNetwork(String),
+    InvalidFelt { key: String, value: String },
+    InvalidNumber { key: String, value: String },
+    InvalidValue { key: String, value: String },
+    InvalidFilter { filter: String, reason: String },
+    UnreachableStorage { url: String, reason: String },
+    /// Several problems found at once.
+    Multiple(Vec<ConfigError>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Args(e) => write!(f, "{}", e),
+            ConfigError::File { path, reason } => write!(f, "Invalid config file {}: {}", path, reason),
+            ConfigError::MissingKey(key) => write!(f, "Missing {}", key),
+            ConfigError::InvalidNetwork(value) => write!(f, "Invalid network name: {}", value),
+            ConfigError::InvalidFelt { key, value } => {
+                write!(f, "{} must be a valid felt, got {:?}", key, value)
+            }
+            ConfigError::InvalidNumber { key, value } => {
+                write!(f, "{} must be a valid number, got {:?}", key, value)
+            }
+            ConfigError::InvalidValue { key, value } => write!(f, "Invalid {}: {:?}", key, value),
+            ConfigError::InvalidFilter { filter, reason } => {
+                write!(f, "Invalid filter {:?}: {}", filter, reason)
+            }
+            ConfigError::UnreachableStorage { url, reason } => {
+                write!(f, "Storage at {} is unreachable: {}", url, reason)
+            }
+            ConfigError::Multiple(errors) => {
+                write!(f, "{} configuration problem(s):", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  - {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{CheckpointBackend, ConfigError, SinkKind, SourceKind};
+
+/// Contents of a `kanshi.toml` / `kanshi.yaml` file. Every field is optional,
+/// CLI arguments and environment variables take precedence over it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub network: Option<String>,
+    pub stream_url: Option<String>,
+    pub apibara_key: Option<String>,
+    pub storage_url: Option<String>,
+    pub starting_block: Option<u64>,
+    pub ending_block: Option<u64>,
+    pub persist_backfill_state: Option<bool>,
+    pub include_transactions: Option<bool>,
+    pub include_receipts: Option<bool>,
+    pub include_state_updates: Option<bool>,
+    pub write_path: Option<String>,
+    pub finality: Option<String>,
+    pub channel_capacity: Option<usize>,
+    #[serde(default)]
+    pub contracts: Vec<FileContract>,
+    #[serde(default)]
+    pub retry: FileRetry,
+    #[serde(default)]
+    pub source: FileSource,
+    #[serde(default)]
+    pub sink: FileSink,
+    #[serde(default)]
+    pub checkpoint: FileCheckpoint,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileContract {
+    pub name: Option<String>,
+    pub address: String,
+    /// Contract class or ABI JSON file used to decode the events.
+    pub abi: Option<String>,
+    #[serde(default)]
+    pub events: Vec<FileEventFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileEventFilter {
+    pub name: String,
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRetry {
+    pub initial_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub multiplier: Option<f64>,
+    pub jitter: Option<f64>,
+    pub max_retries: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSource {
+    pub kind: Option<SourceKind>,
+    pub rpc_url: Option<String>,
+    pub poll_interval_ms: Option<u64>,
+    pub replay_path: Option<String>,
+    pub record_path: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSink {
+    pub kind: Option<SinkKind>,
+    pub key_prefix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileCheckpoint {
+    pub backend: Option<CheckpointBackend>,
+    pub indexer_id: Option<String>,
+}
+
+impl FileConfig {
+    /// Loads a TOML or YAML file, picked from the file extension.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let error = |reason: String| ConfigError::File {
+            path: path.display().to_string(),
+            reason,
+        };
+
+        let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        match extension.as_deref() {
+            Some("toml") => toml::from_str(&content).map_err(|e| error(e.to_string())),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&content).map_err(|e| error(e.to_string()))
+            }
+            _ => Err(error("expected a .toml, .yaml or .yml file".to_string())),
+        }
+    }
+}
@@ -1,44 +1,568 @@
+mod error;
+mod file;
+
+use std::collections::HashMap;
 use std::env;
-use clap::{Arg, Command};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use apibara_sdk::Uri;
+use clap::error::ErrorKind;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use redis::{ConnectionAddr, IntoConnectionInfo};
+use serde::{Deserialize, Serialize};
 use starknet::core::types::Felt;
-use anyhow::Result;
+use starknet::core::utils::{get_selector_from_name, starknet_keccak};
+use starknet::providers::Url;
+
+use crate::abi::ContractAbi;
+
+pub use error::ConfigError;
+use file::FileConfig;
+
+/// How long `Config::check_storage` waits for the storage backend.
+const STORAGE_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+const DEFAULT_RPC_POLL_INTERVAL_MS: u64 = 2_000;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub storage_url: String,
+    /// Empty when streaming from a custom endpoint without authentication,
+    /// or from the `rpc` source.
     pub apibara_key: String,
     pub network: NetworkName,
-    pub contract_address: Felt,
-    // pub filter: String,
+    /// Overrides the DNA endpoint of `network`, e.g. a proxy in front of a
+    /// hosted network, which still needs its API key.
+    pub stream_url: Option<String>,
+    /// Where blocks and events are read from.
+    pub source: SourceConfig,
+    pub contracts: Vec<ContractConfig>,
     pub starting_block: u64,
-    pub write_path: String
+    /// Last block to index, streaming never stops when `None`.
+    pub ending_block: Option<u64>,
+    /// Whether a bounded run reads and updates the resume state at
+    /// `write_path`, off by default so backfills don't move it.
+    pub persist_backfill_state: bool,
+    /// Attach the transaction that emitted each event to the records.
+    pub include_transactions: bool,
+    /// Attach the receipt of that transaction to the records.
+    pub include_receipts: bool,
+    /// Stream storage diffs, deployments and nonce updates of the
+    /// configured contracts.
+    pub include_state_updates: bool,
+    /// State file of the `file` checkpoint backend.
+    pub write_path: String,
+    pub checkpoint: CheckpointConfig,
+    /// Finality of the data requested from the stream.
+    pub finality: Finality,
+    pub retry: RetryConfig,
+    pub sink: SinkConfig,
+    /// Messages buffered between the indexer and its consumers before the
+    /// stream is throttled.
+    pub channel_capacity: usize,
+    /// Only check the configuration and exit (`validate-config` subcommand).
+    pub validate_only: bool,
+}
+
+/// Defaults used for anything left unset, handy to build a configuration in
+/// code: `Config { contracts, ..Config::default() }`.
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            storage_url: "redis://127.0.0.1:6379".to_string(),
+            apibara_key: String::new(),
+            network: NetworkName::Mainnet,
+            stream_url: None,
+            source: SourceConfig {
+                kind: SourceKind::Apibara,
+                rpc_url: None,
+                poll_interval_ms: DEFAULT_RPC_POLL_INTERVAL_MS,
+                replay_path: None,
+                record_path: None,
+            },
+            contracts: Vec::new(),
+            starting_block: 0,
+            ending_block: None,
+            persist_backfill_state: false,
+            include_transactions: false,
+            include_receipts: false,
+            include_state_updates: false,
+            write_path: "indexer_state.json".to_string(),
+            checkpoint: CheckpointConfig {
+                backend: CheckpointBackend::File,
+                indexer_id: "default".to_string(),
+            },
+            finality: Finality::Pending,
+            retry: RetryConfig::default(),
+            sink: SinkConfig {
+                kind: SinkKind::Stdout,
+                key_prefix: "kanshi:event".to_string(),
+            },
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            validate_only: false,
+        }
+    }
+}
+
+/// Where the indexer checkpoint is persisted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointConfig {
+    pub backend: CheckpointBackend,
+    /// Identifies this indexer's checkpoint in a shared storage backend.
+    pub indexer_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointBackend {
+    /// JSON file at `write_path`.
+    File,
+    /// The `StorageManager` backend at `storage_url`.
+    Storage,
+}
+
+impl CheckpointBackend {
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        match input.to_lowercase().as_str() {
+            "file" => Ok(CheckpointBackend::File),
+            "storage" => Ok(CheckpointBackend::Storage),
+            _ => Err(ConfigError::InvalidValue {
+                key: "CHECKPOINT_BACKEND".to_string(),
+                value: input.to_string(),
+            }),
+        }
+    }
+}
+
+/// Where the indexer reads blocks and events from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceConfig {
+    pub kind: SourceKind,
+    /// Starknet JSON-RPC endpoint polled by the `rpc` source.
+    pub rpc_url: Option<String>,
+    /// How often the `rpc` source checks for new blocks once caught up.
+    pub poll_interval_ms: u64,
+    /// Recording read by the `replay` source.
+    pub replay_path: Option<PathBuf>,
+    /// Appends every message the source streams to this file, for the
+    /// `replay` source to read back.
+    pub record_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// The Apibara DNA stream of `network`.
+    Apibara,
+    /// A Starknet node at `rpc_url`, polled with `starknet_getEvents`.
+    Rpc,
+    /// Messages recorded at `replay_path`, the indexer stops at the end.
+    Replay,
+}
+
+impl SourceKind {
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        match input.to_lowercase().as_str() {
+            "apibara" => Ok(SourceKind::Apibara),
+            "rpc" => Ok(SourceKind::Rpc),
+            "replay" => Ok(SourceKind::Replay),
+            _ => Err(ConfigError::InvalidValue {
+                key: "SOURCE".to_string(),
+                value: input.to_string(),
+            }),
+        }
+    }
+}
+
+/// Where consumed events end up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkConfig {
+    pub kind: SinkKind,
+    /// Prefix of the keys written by the storage sink.
+    pub key_prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// Print events to stdout.
+    Stdout,
+    /// Write events to the `StorageManager` backend at `storage_url`.
+    Storage,
+}
+
+impl SinkKind {
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        match input.to_lowercase().as_str() {
+            "stdout" => Ok(SinkKind::Stdout),
+            "storage" => Ok(SinkKind::Storage),
+            _ => Err(ConfigError::InvalidValue {
+                key: "SINK".to_string(),
+                value: input.to_string(),
+            }),
+        }
+    }
+}
+
+/// Data finality, from the least to the most final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Finality {
+    /// Data from the pending block, may still change.
+    Pending,
+    /// Data from blocks accepted on L2, may still be reorganized.
+    Accepted,
+    /// Data from blocks finalized on L1.
+    Finalized,
+}
+
+impl Finality {
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        match input.to_lowercase().as_str() {
+            "pending" => Ok(Finality::Pending),
+            "accepted" => Ok(Finality::Accepted),
+            "finalized" => Ok(Finality::Finalized),
+            _ => Err(ConfigError::InvalidValue {
+                key: "FINALITY".to_string(),
+                value: input.to_string(),
+            }),
+        }
+    }
+}
+
+/// Reconnection policy for the Apibara stream.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    /// Random extra delay, as a fraction of the computed delay.
+    pub jitter: f64,
+    /// Consecutive failures allowed before giving up, `None` retries forever.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 500,
+            max_delay_ms: 60_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_retries: None,
+        }
+    }
+}
+
+/// A contract whose events are indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractConfig {
+    /// Label attached to every event emitted by this contract, defaults to
+    /// the hex address.
+    pub name: String,
+    pub address: Felt,
+    /// Events to stream, every event of the contract when empty.
+    pub events: Vec<EventFilterConfig>,
+    /// Contract class (or bare ABI) JSON file used to decode the events.
+    pub abi_path: Option<PathBuf>,
+    /// ABI read from `abi_path` when the configuration is loaded, see
+    /// `Config::load_abis`. Can also be set directly in code.
+    pub abi: Option<Arc<ContractAbi>>,
+}
+
+/// Selects events by selector and, optionally, the keys that follow it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventFilterConfig {
+    /// Event name as written in the contract (e.g. `Transfer`), or the raw
+    /// selector when given as hex.
+    pub name: String,
+    pub selector: Felt,
+    pub keys: Vec<Felt>,
+}
+
+impl EventFilterConfig {
+    pub fn new(name: &str, keys: Vec<Felt>) -> Result<Self, ConfigError> {
+        let selector = if name.starts_with("0x") {
+            parse_felt("EVENT_FILTERS", name)?
+        } else {
+            get_selector_from_name(name).map_err(|e| ConfigError::InvalidFilter {
+                filter: name.to_string(),
+                reason: e.to_string(),
+            })?
+        };
+
+        Ok(EventFilterConfig {
+            name: name.to_string(),
+            selector,
+            keys,
+        })
+    }
+
+    /// Keys to match with Apibara, the selector always comes first.
+    pub fn filter_keys(&self) -> Vec<Felt> {
+        let mut keys = vec![self.selector];
+        keys.extend(self.keys.iter().copied());
+        keys
+    }
+}
+
+/// A `--filter` value: `[CONTRACT/]EVENT[:KEY,...]`, where `CONTRACT` is a
+/// configured contract name or address and defaults to every contract.
+#[derive(Debug, Clone, PartialEq)]
+struct FilterSpec {
+    contract: Option<String>,
+    filter: EventFilterConfig,
+}
+
+impl FilterSpec {
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        let input = input.trim();
+        let (contract, rest) = match input.split_once('/') {
+            Some((contract, rest)) => (Some(contract.trim().to_string()), rest),
+            None => (None, input),
+        };
+        let (event, keys) = match rest.split_once(':') {
+            Some((event, keys)) => (event.trim(), keys),
+            None => (rest.trim(), ""),
+        };
+        if event.is_empty() {
+            return Err(ConfigError::InvalidFilter {
+                filter: input.to_string(),
+                reason: "missing event name".to_string(),
+            });
+        }
+        let keys = keys
+            .split(',')
+            .filter(|k| !k.trim().is_empty())
+            .map(|k| parse_felt("EVENT_FILTERS", k))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(FilterSpec {
+            contract,
+            filter: EventFilterConfig::new(event, keys)?,
+        })
+    }
+
+    fn applies_to(&self, contract: &ContractConfig) -> bool {
+        match &self.contract {
+            None => true,
+            Some(target) => {
+                target == &contract.name
+                    || Felt::from_hex(target).map(|a| a == contract.address).unwrap_or(false)
+            }
+        }
+    }
+}
+
+impl ContractConfig {
+    /// Parses `0x...` or `name=0x...`.
+    fn from_str(input: &str) -> Result<Self, ConfigError> {
+        let input = input.trim();
+        let (name, address) = match input.split_once('=') {
+            Some((name, address)) => (Some(name.trim()), address.trim()),
+            None => (None, input),
+        };
+        let address = parse_felt("CONTRACT_ADDRESS", address)?;
+
+        Ok(ContractConfig {
+            name: name
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| address.to_hex_string()),
+            address,
+            events: Vec::new(),
+            abi_path: None,
+            abi: None,
+        })
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Default DNA server of a local devnet.
+const DEVNET_STREAM_URL: &str = "http://127.0.0.1:7171";
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum NetworkName {
     Mainnet,
     Sepolia,
+    /// Any Apibara-compatible DNA server, e.g. a self-hosted one in front of
+    /// a local devnet.
+    Custom { name: String, uri: String },
 }
 
 impl NetworkName {
-    fn from_str(input: &str) -> Result<Self, String> {
-        match input.to_lowercase().as_str() {
-            "mainnet" => Ok(NetworkName::Mainnet),
-            "sepolia" => Ok(NetworkName::Sepolia),
-            _ => Err(format!("Invalid network name: {}", input)),
+    /// Resolves `--network`, `stream_url` being the endpoint of a custom
+    /// network. Known networks keep their identity when it is set, only
+    /// their endpoint is overridden by `Config::stream_url`.
+    fn from_str(input: &str, stream_url: Option<String>) -> Result<Self, ConfigError> {
+        let name = input.to_lowercase();
+        let network = match (name.as_str(), stream_url) {
+            ("mainnet", _) => NetworkName::Mainnet,
+            ("sepolia", _) => NetworkName::Sepolia,
+            ("devnet", uri) => NetworkName::Custom {
+                name: name.clone(),
+                uri: uri.unwrap_or_else(|| DEVNET_STREAM_URL.to_string()),
+            },
+            (_, Some(uri)) => NetworkName::Custom { name: name.clone(), uri },
+            _ => return Err(ConfigError::InvalidNetwork(input.to_string())),
+        };
+
+        Ok(network)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            NetworkName::Mainnet => "mainnet",
+            NetworkName::Sepolia => "sepolia",
+            NetworkName::Custom { name, .. } => name,
+        }
+    }
+
+    pub fn stream_uri(&self) -> Result<Uri, ConfigError> {
+        match self {
+            NetworkName::Mainnet => Ok(Uri::from_static("https://mainnet.starknet.a5a.ch")),
+            NetworkName::Sepolia => Ok(Uri::from_static("https://sepolia.starknet.a5a.ch")),
+            NetworkName::Custom { uri, .. } => uri.parse().map_err(|_| ConfigError::InvalidValue {
+                key: "STREAM_URL".to_string(),
+                value: uri.clone(),
+            }),
         }
     }
+
+    /// Hosted Apibara endpoints need an API key, self-hosted ones usually don't.
+    pub fn requires_api_key(&self) -> bool {
+        !matches!(self, NetworkName::Custom { .. })
+    }
 }
 
-impl Config {
-    pub fn new() -> Result<Self> {
-        dotenv::dotenv().ok();
+/// Problems found while resolving the configuration, reported all at once.
+#[derive(Debug, Default)]
+struct Problems(Vec<ConfigError>);
+
+impl Problems {
+    fn check<T>(&mut self, result: Result<T, ConfigError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.0.push(e);
+                None
+            }
+        }
+    }
+
+    fn into_result(mut self) -> Result<(), ConfigError> {
+        match self.0.len() {
+            0 => Ok(()),
+            1 => Err(self.0.remove(0)),
+            _ => Err(ConfigError::Multiple(self.0)),
+        }
+    }
+}
+
+/// CLI arguments and environment variables the configuration is read from.
+struct Sources<'a> {
+    matches: &'a ArgMatches,
+    env: &'a HashMap<String, String>,
+}
+
+impl Sources<'_> {
+    fn var(&self, var: &str) -> Option<String> {
+        self.env.get(var).cloned()
+    }
+
+    /// Resolves a single value with CLI > env > file precedence.
+    fn value(&self, arg: &str, var: &str, file: Option<String>) -> Option<String> {
+        self.matches
+            .get_one::<String>(arg)
+            .cloned()
+            .or_else(|| self.var(var))
+            .or(file)
+    }
+
+    /// Resolves a `SetTrue` flag, which can only be turned on from the CLI.
+    fn flag(&self, arg: &str, var: &str, file: Option<bool>, problems: &mut Problems) -> bool {
+        self.matches.get_flag(arg)
+            || match self.var(var) {
+                Some(v) => problems.check(parse_bool(var, &v)).unwrap_or_default(),
+                None => file.unwrap_or_default(),
+            }
+    }
+
+    /// Resolves a list from the CLI or the environment, env values being
+    /// split on `separator`.
+    fn list(&self, arg: &str, var: &str, separator: char) -> Option<Vec<String>> {
+        if let Some(values) = self.matches.get_many::<String>(arg) {
+            return Some(values.cloned().collect());
+        }
+        self.var(var).map(|v| {
+            v.split(separator)
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.to_string())
+                .collect()
+        })
+    }
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError::InvalidNumber {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn read_abis(contracts: &mut [ContractConfig], problems: &mut Problems) {
+    for contract in contracts.iter_mut().filter(|c| c.abi.is_none()) {
+        let Some(path) = &contract.abi_path else {
+            continue;
+        };
+        contract.abi = problems.check(ContractAbi::load(path).map(Arc::new).map_err(|e| {
+            ConfigError::InvalidValue {
+                key: "ABI_PATHS".to_string(),
+                value: format!("{:#}", e),
+            }
+        }));
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn parse_felt(key: &str, value: &str) -> Result<Felt, ConfigError> {
+    Felt::from_hex(value.trim()).map_err(|_| ConfigError::InvalidFelt {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
 
-        // Parse CLI arguments
-        let matches = Command::new("Welcome to Kanshi!!")
+impl Config {
+    fn command() -> Command {
+        Command::new("Welcome to Kanshi!!")
             .version("1.0")
             .author("RougeDevs")
             .about("Configures and runs the application")
+            .subcommand(
+                Command::new("validate-config")
+                    .about("Checks the configuration, reports every problem found and exits"),
+            )
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .value_name("KANSHI_CONFIG")
+                    .help("Sets the TOML or YAML configuration file")
+                    .num_args(1)
+                    .global(true),
+            )
             .arg(
                 Arg::new("redis-url")
                     .long("redis-url")
@@ -60,53 +584,992 @@ impl Config {
                     .help("Sets the starting block")
                     .num_args(1),
             )
+            .arg(
+                Arg::new("ending-block")
+                    .long("ending-block")
+                    .value_name("ENDING_BLOCK")
+                    .help("Stops after indexing this block (one-shot backfill)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("persist-backfill-state")
+                    .long("persist-backfill-state")
+                    .help("Reads and updates the resume state during a bounded backfill")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("include-transactions")
+                    .long("include-transactions")
+                    .help("Attaches the transaction that emitted each event")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("include-receipts")
+                    .long("include-receipts")
+                    .help("Attaches the receipt of the transaction that emitted each event")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("include-state-updates")
+                    .long("include-state-updates")
+                    .help("Streams storage diffs, deployments and nonce updates of the contracts")
+                    .action(ArgAction::SetTrue),
+            )
             .arg(
                 Arg::new("contract-address")
                 .long("contract-address")
                 .value_name("CONTRACT_ADDRESS")
-                .help("Set contract address to listen to")
+                .help("Add a contract address to listen to, optionally named as name=0x... (repeatable)")
+                .num_args(1)
+                .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("filter")
+                .long("filter")
+                .value_name("[CONTRACT/]EVENT[:KEY,...]")
+                .help("Only stream the given event, optionally for one contract and with extra keys (repeatable)")
+                .num_args(1)
+                .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("abi")
+                .long("abi")
+                .value_name("CONTRACT=PATH")
+                .help("Decodes the events of a contract, named or by address, with the ABI in a contract class JSON file (repeatable)")
                 .num_args(1)
+                .action(ArgAction::Append)
             )
-            // .arg(
-            //     Arg::new("filter")
-            //     .long("filter")
-            //     .value_name("filter")
-            //     .help("Set event filters")
-            //     .num_args(10)
-            // )
             .arg(
                 Arg::new("network")
                     .long("network")
                     .value_name("NETWORK")
-                    .help("Sets the network (Mainnet or Sepolia)")
-                    .num_args(1),
-            )
-            .get_matches();
-
-        Ok(Config {
-            storage_url: matches
-                .get_one::<String>("redis-url")
-                .cloned()
-                .unwrap_or_else(|| env::var("REDIS_URL").unwrap_or_else(|_| "redis://123.0.0.1:6379".to_string())),
-            apibara_key: matches
-                .get_one::<String>("apibara-key")
-                .cloned()
-                .unwrap_or_else(|| env::var("APIBARA_KEY").expect("Missing APIBARA_KEY")),
-            network: matches
-                .get_one::<String>("network")
-                .map(|v| NetworkName::from_str(v).expect("Invalid network value"))
-                .unwrap_or(NetworkName::Mainnet),
-            contract_address: Felt::from_hex(&env::var("CONTRACT_ADDRESS").expect("Missing CONTRACT_ADDRESS"))?,
-            starting_block: matches
-                .get_one::<String>("starting-block")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or_else(|| {
-                    env::var("STARTING_BLOCK")
-                        .unwrap_or_else(|_| "0".to_string())
-                        .parse()
-                        .expect("STARTING_BLOCK must be a valid number")
+                    .help("Sets the network (Mainnet, Sepolia, Devnet or a custom name used with --stream-url)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("stream-url")
+                    .long("stream-url")
+                    .value_name("STREAM_URL")
+                    .help("Overrides the Apibara DNA endpoint, e.g. http://127.0.0.1:7171")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("source")
+                    .long("source")
+                    .value_name("SOURCE")
+                    .help("Sets where blocks are read from (apibara, rpc or replay)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("rpc-url")
+                    .long("rpc-url")
+                    .value_name("RPC_URL")
+                    .help("Sets the Starknet JSON-RPC endpoint of the rpc source, e.g. http://127.0.0.1:5050")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("replay-path")
+                    .long("replay-path")
+                    .value_name("REPLAY_PATH")
+                    .help("Sets the recording read by the replay source")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("record-path")
+                    .long("record-path")
+                    .value_name("RECORD_PATH")
+                    .help("Records every message streamed by the source to this file")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("checkpoint-backend")
+                    .long("checkpoint-backend")
+                    .value_name("CHECKPOINT_BACKEND")
+                    .help("Sets where the checkpoint is stored (file or storage)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("indexer-id")
+                    .long("indexer-id")
+                    .value_name("INDEXER_ID")
+                    .help("Sets the id of the checkpoint in the storage backend")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("finality")
+                    .long("finality")
+                    .value_name("FINALITY")
+                    .help("Sets the data finality (pending, accepted or finalized)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("write-path")
+                    .long("write-path")
+                    .value_name("WRITE_PATH")
+                    .help("Sets the path of the indexer state file")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("retry-initial-delay")
+                    .long("retry-initial-delay")
+                    .value_name("RETRY_INITIAL_DELAY_MS")
+                    .help("Sets the first reconnection delay in milliseconds")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("retry-max-delay")
+                    .long("retry-max-delay")
+                    .value_name("RETRY_MAX_DELAY_MS")
+                    .help("Sets the maximum reconnection delay in milliseconds")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("retry-max-attempts")
+                    .long("retry-max-attempts")
+                    .value_name("RETRY_MAX_ATTEMPTS")
+                    .help("Sets how many consecutive reconnections to attempt (0 retries forever)")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("retry-multiplier")
+                    .long("retry-multiplier")
+                    .value_name("RETRY_MULTIPLIER")
+                    .help("Sets the factor applied to the reconnection delay after each failure")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("retry-jitter")
+                    .long("retry-jitter")
+                    .value_name("RETRY_JITTER")
+                    .help("Sets the random extra reconnection delay, as a fraction of the delay")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("channel-capacity")
+                    .long("channel-capacity")
+                    .value_name("CHANNEL_CAPACITY")
+                    .help("Sets how many messages are buffered for consumers before the stream waits")
+                    .num_args(1),
+            )
+            .arg(
+                Arg::new("sink")
+                    .long("sink")
+                    .value_name("SINK")
+                    .help("Sets where events are written (stdout or storage)")
+                    .num_args(1),
+            )
+    }
+
+    /// Loads the configuration from the process CLI arguments, environment
+    /// (including `.env`) and the optional config file, then makes sure the
+    /// storage backend is reachable when it is used. Every problem found,
+    /// storage included, is reported at once.
+    pub async fn new() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+
+        let env: HashMap<String, String> = env::vars().collect();
+        match Self::load(env::args_os(), &env).await {
+            // Let clap print help and version the usual way, usage errors
+            // are reported like any other configuration problem
+            Err(ConfigError::Args(e)) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => {
+                e.exit()
+            }
+            result => result,
+        }
+    }
+
+    /// Same as `from_sources`, also checking that the storage backend is
+    /// reachable when it is used.
+    async fn load<I, T>(args: I, env: &HashMap<String, String>) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let (config, mut problems) = Self::resolve(args, env)?;
+        if config.uses_storage() {
+            problems.check(config.check_storage().await);
+        }
+        problems.into_result()?;
+
+        Ok(config)
+    }
+
+    /// Resolves the configuration from explicit CLI arguments (including the
+    /// binary name) and environment variables, with CLI > env > config file >
+    /// defaults precedence. Does not touch the network.
+    pub fn from_sources<I, T>(args: I, env: &HashMap<String, String>) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let (config, problems) = Self::resolve(args, env)?;
+        problems.into_result()?;
+
+        Ok(config)
+    }
+
+    /// Same as `from_sources`, returning the configuration along with its
+    /// problems so more checks can be added to them. Only fails on usage
+    /// errors.
+    fn resolve<I, T>(args: I, env: &HashMap<String, String>) -> Result<(Self, Problems), ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command()
+            .try_get_matches_from(args)
+            .map_err(ConfigError::Args)?;
+        let sources = Sources { matches: &matches, env };
+        let mut problems = Problems::default();
+
+        let file = match sources.value("config", "KANSHI_CONFIG", None) {
+            Some(path) => problems
+                .check(FileConfig::load(&PathBuf::from(path)))
+                .unwrap_or_default(),
+            None => FileConfig::default(),
+        };
+
+        let defaults = Config::default();
+        let storage_url = sources
+            .value("redis-url", "REDIS_URL", file.storage_url.clone())
+            .unwrap_or(defaults.storage_url);
+        let stream_url = sources.value("stream-url", "STREAM_URL", file.stream_url.clone());
+        let network = match sources.value("network", "NETWORK", file.network.clone()) {
+            Some(v) => problems.check(NetworkName::from_str(&v, stream_url.clone())),
+            None if stream_url.is_some() => problems.check(NetworkName::from_str("custom", stream_url.clone())),
+            None => Some(NetworkName::Mainnet),
+        };
+        let source = Self::resolve_source(&sources, &file, &mut problems);
+        let apibara_key = sources.value("apibara-key", "APIBARA_KEY", file.apibara_key.clone());
+        let starting_block = match sources.value(
+            "starting-block",
+            "STARTING_BLOCK",
+            file.starting_block.map(|b| b.to_string()),
+        ) {
+            Some(v) => problems.check(parse_number("STARTING_BLOCK", &v)),
+            None => Some(0),
+        };
+        let ending_block = match sources.value(
+            "ending-block",
+            "ENDING_BLOCK",
+            file.ending_block.map(|b| b.to_string()),
+        ) {
+            Some(v) => problems.check(parse_number::<u64>("ENDING_BLOCK", &v)),
+            None => None,
+        };
+        let persist_backfill_state = sources.flag(
+            "persist-backfill-state",
+            "PERSIST_BACKFILL_STATE",
+            file.persist_backfill_state,
+            &mut problems,
+        );
+        let include_transactions = sources.flag(
+            "include-transactions",
+            "INCLUDE_TRANSACTIONS",
+            file.include_transactions,
+            &mut problems,
+        );
+        let include_receipts = sources.flag(
+            "include-receipts",
+            "INCLUDE_RECEIPTS",
+            file.include_receipts,
+            &mut problems,
+        );
+        let include_state_updates = sources.flag(
+            "include-state-updates",
+            "INCLUDE_STATE_UPDATES",
+            file.include_state_updates,
+            &mut problems,
+        );
+        let write_path = sources
+            .value("write-path", "WRITE_PATH", file.write_path.clone())
+            .unwrap_or(defaults.write_path);
+
+        let checkpoint = CheckpointConfig {
+            backend: match sources.value("checkpoint-backend", "CHECKPOINT_BACKEND", None) {
+                Some(v) => problems.check(CheckpointBackend::from_str(&v)).unwrap_or(CheckpointBackend::File),
+                None => file.checkpoint.backend.unwrap_or(CheckpointBackend::File),
+            },
+            indexer_id: sources
+                .value("indexer-id", "INDEXER_ID", file.checkpoint.indexer_id.clone())
+                .unwrap_or(defaults.checkpoint.indexer_id),
+        };
+
+        let finality = match sources.value("finality", "FINALITY", file.finality.clone()) {
+            Some(v) => problems.check(Finality::from_str(&v)),
+            None if source.kind == SourceKind::Rpc => Some(Finality::Accepted),
+            None => Some(Finality::Pending),
+        };
+
+        let retry = Self::resolve_retry(&sources, &file, &mut problems);
+        let mut contracts = Self::resolve_contracts(&sources, &file, &mut problems);
+        // Parsed once here, the indexer reuses them
+        read_abis(&mut contracts, &mut problems);
+
+        let sink = SinkConfig {
+            kind: match sources.value("sink", "SINK", None) {
+                Some(v) => problems.check(SinkKind::from_str(&v)).unwrap_or(SinkKind::Stdout),
+                None => file.sink.kind.unwrap_or(SinkKind::Stdout),
+            },
+            key_prefix: sources
+                .var("SINK_KEY_PREFIX")
+                .or(file.sink.key_prefix.clone())
+                .unwrap_or(defaults.sink.key_prefix),
+        };
+
+        let channel_capacity = match sources.value(
+            "channel-capacity",
+            "CHANNEL_CAPACITY",
+            file.channel_capacity.map(|c| c.to_string()),
+        ) {
+            Some(v) => problems.check(parse_number("CHANNEL_CAPACITY", &v)),
+            None => Some(DEFAULT_CHANNEL_CAPACITY),
+        };
+
+        let config = Config {
+            storage_url,
+            apibara_key: apibara_key.unwrap_or_default(),
+            network: network.unwrap_or(NetworkName::Mainnet),
+            stream_url,
+            source,
+            contracts,
+            starting_block: starting_block.unwrap_or_default(),
+            ending_block,
+            persist_backfill_state,
+            include_transactions,
+            include_receipts,
+            include_state_updates,
+            write_path,
+            checkpoint,
+            finality: finality.unwrap_or(Finality::Pending),
+            retry,
+            sink,
+            channel_capacity: channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY),
+            validate_only: matches.subcommand_matches("validate-config").is_some(),
+        };
+        // The same checks apply to a `Config` built in code
+        config.check_values(&mut problems, true);
+
+        Ok((config, problems))
+    }
+
+    /// Checks the values that can't work whatever they were loaded from,
+    /// e.g. for a configuration built in code. `from_sources` already runs
+    /// these checks.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Problems::default();
+        self.check_values(&mut problems, true);
+        problems.into_result()
+    }
+
+    /// Same as `validate`, without the source settings, for an indexer
+    /// given its source in code.
+    pub(crate) fn validate_without_source(&self) -> Result<(), ConfigError> {
+        let mut problems = Problems::default();
+        self.check_values(&mut problems, false);
+        problems.into_result()
+    }
+
+    /// Parses the ABI of the contracts that have an `abi_path` and no `abi`
+    /// yet. `from_sources` already loads them.
+    pub fn load_abis(&mut self) -> Result<(), ConfigError> {
+        let mut problems = Problems::default();
+        read_abis(&mut self.contracts, &mut problems);
+        problems.into_result()
+    }
+
+    fn check_values(&self, problems: &mut Problems, check_source: bool) {
+        problems.check(self.stream_uri());
+        if check_source {
+            match self.source.kind {
+                // Only the hosted Apibara endpoints need a key
+                SourceKind::Apibara if self.network.requires_api_key() && self.apibara_key.is_empty() => {
+                    problems.0.push(ConfigError::MissingKey("APIBARA_KEY".to_string()));
+                }
+                SourceKind::Rpc if self.source.rpc_url.is_none() => {
+                    problems.0.push(ConfigError::MissingKey("RPC_URL".to_string()));
+                }
+                SourceKind::Replay => match &self.source.replay_path {
+                    Some(path) if !path.is_file() => problems.0.push(ConfigError::File {
+                        path: path.display().to_string(),
+                        reason: "recording not found".to_string(),
+                    }),
+                    None => problems.0.push(ConfigError::MissingKey("REPLAY_PATH".to_string())),
+                    _ => {}
+                },
+                _ => {}
+            }
+            // Nodes don't serve pending blocks by number
+            if self.source.kind == SourceKind::Rpc && self.finality == Finality::Pending {
+                problems.0.push(ConfigError::InvalidValue {
+                    key: "FINALITY".to_string(),
+                    value: "pending is not supported by the rpc source, use accepted or finalized".to_string(),
+                });
+            }
+            if let Some(url) = &self.source.rpc_url {
+                if Url::parse(url).is_err() {
+                    problems.0.push(ConfigError::InvalidValue {
+                        key: "RPC_URL".to_string(),
+                        value: url.clone(),
+                    });
+                }
+            }
+        }
+
+        if self.contracts.is_empty() {
+            problems.0.push(ConfigError::MissingKey("CONTRACT_ADDRESS".to_string()));
+        }
+        // ABIs are parsed by `load_abis`, only make sure the ones it hasn't
+        // read yet exist
+        for contract in self.contracts.iter().filter(|c| c.abi.is_none()) {
+            match &contract.abi_path {
+                Some(path) if !path.is_file() => problems.0.push(ConfigError::File {
+                    path: path.display().to_string(),
+                    reason: "ABI not found".to_string(),
                 }),
-            write_path: env::var("WRITE_PATH").unwrap_or_else(|_| "indexer_state.json".to_string())
-        })
+                _ => {}
+            }
+        }
+        if let Some(end) = self.ending_block {
+            if end < self.starting_block {
+                problems.0.push(ConfigError::InvalidValue {
+                    key: "ENDING_BLOCK".to_string(),
+                    value: format!("{} is before STARTING_BLOCK {}", end, self.starting_block),
+                });
+            }
+        }
+        if self.channel_capacity == 0 {
+            problems.0.push(ConfigError::InvalidValue {
+                key: "CHANNEL_CAPACITY".to_string(),
+                value: "0, the channel needs room for at least one message".to_string(),
+            });
+        }
+        // A multiplier below 1 shrinks the delays and a negative jitter could
+        // make them negative
+        if !self.retry.multiplier.is_finite() || self.retry.multiplier < 1.0 {
+            problems.0.push(ConfigError::InvalidValue {
+                key: "RETRY_MULTIPLIER".to_string(),
+                value: self.retry.multiplier.to_string(),
+            });
+        }
+        if !self.retry.jitter.is_finite() || self.retry.jitter < 0.0 {
+            problems.0.push(ConfigError::InvalidValue {
+                key: "RETRY_JITTER".to_string(),
+                value: self.retry.jitter.to_string(),
+            });
+        }
+    }
+
+    fn resolve_source(sources: &Sources, file: &FileConfig, problems: &mut Problems) -> SourceConfig {
+        let kind = match sources.value("source", "SOURCE", None) {
+            Some(v) => problems.check(SourceKind::from_str(&v)).unwrap_or(SourceKind::Apibara),
+            None => file.source.kind.unwrap_or(SourceKind::Apibara),
+        };
+
+        let rpc_url = sources.value("rpc-url", "RPC_URL", file.source.rpc_url.clone());
+
+        let poll_interval_ms = match sources
+            .var("RPC_POLL_INTERVAL_MS")
+            .or(file.source.poll_interval_ms.map(|ms| ms.to_string()))
+        {
+            Some(v) => problems
+                .check(parse_number("RPC_POLL_INTERVAL_MS", &v))
+                .unwrap_or(DEFAULT_RPC_POLL_INTERVAL_MS),
+            None => DEFAULT_RPC_POLL_INTERVAL_MS,
+        };
+
+        let replay_path = sources
+            .value("replay-path", "REPLAY_PATH", file.source.replay_path.clone())
+            .map(PathBuf::from);
+
+        SourceConfig {
+            kind,
+            rpc_url,
+            poll_interval_ms,
+            replay_path,
+            record_path: sources
+                .value("record-path", "RECORD_PATH", file.source.record_path.clone())
+                .map(PathBuf::from),
+        }
+    }
+
+    fn resolve_retry(sources: &Sources, file: &FileConfig, problems: &mut Problems) -> RetryConfig {
+        let defaults = RetryConfig::default();
+        let mut retry_value = |arg: &str, var: &str, file: Option<u64>| -> Option<u64> {
+            sources
+                .value(arg, var, file.map(|v| v.to_string()))
+                .and_then(|v| problems.check(parse_number(var, &v)))
+        };
+
+        let initial_delay_ms = retry_value("retry-initial-delay", "RETRY_INITIAL_DELAY_MS", file.retry.initial_delay_ms)
+            .unwrap_or(defaults.initial_delay_ms);
+        let max_delay_ms = retry_value("retry-max-delay", "RETRY_MAX_DELAY_MS", file.retry.max_delay_ms)
+            .unwrap_or(defaults.max_delay_ms);
+        let max_retries = match sources
+            .value("retry-max-attempts", "RETRY_MAX_ATTEMPTS", file.retry.max_retries.map(|v| v.to_string()))
+            .and_then(|v| problems.check(parse_number::<u32>("RETRY_MAX_ATTEMPTS", &v)))
+        {
+            Some(0) => None,
+            Some(attempts) => Some(attempts),
+            None => defaults.max_retries,
+        };
+
+        let mut factor_value = |arg: &str, var: &str, file: Option<f64>| -> Option<f64> {
+            sources
+                .value(arg, var, file.map(|v| v.to_string()))
+                .and_then(|v| problems.check(parse_number(var, &v)))
+        };
+
+        RetryConfig {
+            initial_delay_ms,
+            max_delay_ms,
+            max_retries,
+            multiplier: factor_value("retry-multiplier", "RETRY_MULTIPLIER", file.retry.multiplier)
+                .unwrap_or(defaults.multiplier),
+            jitter: factor_value("retry-jitter", "RETRY_JITTER", file.retry.jitter).unwrap_or(defaults.jitter),
+        }
+    }
+
+    fn resolve_contracts(sources: &Sources, file: &FileConfig, problems: &mut Problems) -> Vec<ContractConfig> {
+        let mut contracts = match sources.list("contract-address", "CONTRACT_ADDRESS", ',') {
+            Some(values) => values
+                .iter()
+                .filter_map(|v| problems.check(ContractConfig::from_str(v)))
+                .collect(),
+            None => file
+                .contracts
+                .iter()
+                .filter_map(|c| {
+                    let mut contract = problems.check(ContractConfig::from_str(&c.address))?;
+                    if let Some(name) = &c.name {
+                        contract.name = name.clone();
+                    }
+                    contract.abi_path = c.abi.as_ref().map(PathBuf::from);
+                    for event in &c.events {
+                        let keys = event
+                            .keys
+                            .iter()
+                            .filter_map(|k| problems.check(parse_felt("contracts.events.keys", k)))
+                            .collect();
+                        contract.events.extend(problems.check(EventFilterConfig::new(&event.name, keys)));
+                    }
+                    Some(contract)
+                })
+                .collect::<Vec<_>>(),
+        };
+
+        // Filters are separated by `;` in the environment since keys use `,`
+        if let Some(filters) = sources.list("filter", "EVENT_FILTERS", ';') {
+            // Filters given on the CLI or in the environment replace the file ones
+            for contract in contracts.iter_mut() {
+                contract.events.clear();
+            }
+            for spec in filters.iter().filter_map(|v| problems.check(FilterSpec::from_str(v))) {
+                let mut matched = false;
+                for contract in contracts.iter_mut().filter(|c| spec.applies_to(c)) {
+                    contract.events.push(spec.filter.clone());
+                    matched = true;
+                }
+                if !matched {
+                    problems.0.push(ConfigError::InvalidFilter {
+                        filter: spec.filter.name.clone(),
+                        reason: format!("unknown contract {:?}", spec.contract.unwrap_or_default()),
+                    });
+                }
+            }
+        }
+
+        // ABIs given as CONTRACT=PATH, CONTRACT being a name or an address
+        if let Some(abis) = sources.list("abi", "ABI_PATHS", ',') {
+            for abi in &abis {
+                let Some((contract, path)) = abi.split_once('=') else {
+                    problems.0.push(ConfigError::InvalidValue {
+                        key: "ABI_PATHS".to_string(),
+                        value: abi.clone(),
+                    });
+                    continue;
+                };
+                let address = Felt::from_hex(contract.trim()).ok();
+                match contracts
+                    .iter_mut()
+                    .find(|c| c.name == contract.trim() || Some(c.address) == address)
+                {
+                    Some(contract) => contract.abi_path = Some(PathBuf::from(path.trim())),
+                    None => problems.0.push(ConfigError::InvalidValue {
+                        key: "ABI_PATHS".to_string(),
+                        value: format!("{} (unknown contract)", abi),
+                    }),
+                }
+            }
+        }
+
+        contracts
+    }
+
+    /// Identifies the data this configuration streams (network, contracts,
+    /// filters and finality), stored with checkpoints to detect a state
+    /// written by a different configuration.
+    pub fn fingerprint(&self) -> String {
+        let mut canonical = format!("{}|{}|{:?}", self.network.name(), self.stream_uri_string(), self.finality);
+        for contract in &self.contracts {
+            canonical.push_str(&format!("|{}", contract.address.to_hex_string()));
+            for event in &contract.events {
+                canonical.push_str(&format!(":{}", event.selector.to_hex_string()));
+                for key in &event.keys {
+                    canonical.push_str(&format!(",{}", key.to_hex_string()));
+                }
+            }
+        }
+
+        starknet_keccak(canonical.as_bytes()).to_hex_string()
+    }
+
+    /// Endpoint of the Apibara stream, `stream_url` overriding the one of
+    /// `network`.
+    pub fn stream_uri(&self) -> Result<Uri, ConfigError> {
+        match &self.stream_url {
+            Some(uri) => uri.parse().map_err(|_| ConfigError::InvalidValue {
+                key: "STREAM_URL".to_string(),
+                value: uri.clone(),
+            }),
+            None => self.network.stream_uri(),
+        }
+    }
+
+    fn stream_uri_string(&self) -> String {
+        self.stream_uri().map(|uri| uri.to_string()).unwrap_or_default()
+    }
+
+    /// Whether some part of the pipeline writes to `storage_url`.
+    pub fn uses_storage(&self) -> bool {
+        self.sink.kind == SinkKind::Storage || self.checkpoint.backend == CheckpointBackend::Storage
+    }
+
+    /// Opens a TCP connection to the storage backend to make sure it is
+    /// reachable, without blocking the runtime.
+    pub async fn check_storage(&self) -> Result<(), ConfigError> {
+        let unreachable = |reason: String| ConfigError::UnreachableStorage {
+            url: self.storage_url.clone(),
+            reason,
+        };
+
+        let (host, port) = if self.storage_url.starts_with("postgres") {
+            let pg = tokio_postgres::Config::from_str(&self.storage_url)
+                .map_err(|e| unreachable(e.to_string()))?;
+            let host = match pg.get_hosts().first() {
+                Some(tokio_postgres::config::Host::Tcp(host)) => host.clone(),
+                // Unix sockets are local, nothing to check over TCP
+                Some(_) => return Ok(()),
+                None => "localhost".to_string(),
+            };
+            (host, pg.get_ports().first().copied().unwrap_or(5432))
+        } else {
+            let info = self
+                .storage_url
+                .as_str()
+                .into_connection_info()
+                .map_err(|e| unreachable(e.to_string()))?;
+            match info.addr {
+                ConnectionAddr::Tcp(host, port) => (host, port),
+                ConnectionAddr::TcpTls { host, port, .. } => (host, port),
+                _ => return Ok(()),
+            }
+        };
+
+        let addrs = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| unreachable(e.to_string()))?;
+        let mut last_error = format!("could not resolve {}:{}", host, port);
+        for addr in addrs {
+            match tokio::time::timeout(STORAGE_CONNECT_TIMEOUT, tokio::net::TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => last_error = e.to_string(),
+                Err(_) => last_error = format!("timed out connecting to {}", addr),
+            }
+        }
+
+        Err(unreachable(last_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT: &str = "0x0123";
+    const POOL: &str = "0x0456";
+
+    fn load(args: &[&str], vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let env = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::from_sources(std::iter::once("kanshi").chain(args.iter().copied()), &env)
+    }
+
+    fn problems(result: Result<Config, ConfigError>) -> Vec<ConfigError> {
+        match result {
+            Ok(config) => panic!("expected configuration problems, got {:?}", config),
+            Err(ConfigError::Multiple(problems)) => problems,
+            Err(problem) => vec![problem],
+        }
+    }
+
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("kanshi-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn cli_overrides_env_which_overrides_file() {
+        let path = write_file(
+            "precedence.toml",
+            &format!("starting_block = 10\napibara_key = \"file-key\"\n\n[[contracts]]\naddress = \"{}\"\n", VAULT),
+        );
+        let path = path.to_str().unwrap();
+
+        let config = load(&["--config", path], &[]).unwrap();
+        assert_eq!(config.starting_block, 10);
+        assert_eq!(config.apibara_key, "file-key");
+
+        let config = load(&["--config", path], &[("STARTING_BLOCK", "20")]).unwrap();
+        assert_eq!(config.starting_block, 20);
+        assert_eq!(config.apibara_key, "file-key");
+
+        let config = load(
+            &["--config", path, "--starting-block", "30"],
+            &[("STARTING_BLOCK", "20"), ("APIBARA_KEY", "env-key")],
+        )
+        .unwrap();
+        assert_eq!(config.starting_block, 30);
+        assert_eq!(config.apibara_key, "env-key");
+        assert_eq!(config.contracts.len(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn apibara_key_is_only_required_by_hosted_apibara() {
+        let problems = problems(load(&[], &[("CONTRACT_ADDRESS", VAULT)]));
+        assert!(matches!(problems.as_slice(), [ConfigError::MissingKey(key)] if key == "APIBARA_KEY"));
+
+        let config = load(&[], &[("CONTRACT_ADDRESS", VAULT), ("NETWORK", "devnet")]).unwrap();
+        assert!(config.apibara_key.is_empty());
+
+        let config = load(
+            &["--stream-url", "http://127.0.0.1:7171"],
+            &[("CONTRACT_ADDRESS", VAULT)],
+        )
+        .unwrap();
+        assert_eq!(config.network.name(), "custom");
+
+        // Overriding the endpoint of a hosted network still needs its key
+        let problems = problems(load(
+            &["--network", "mainnet", "--stream-url", "https://proxy.example.com"],
+            &[("CONTRACT_ADDRESS", VAULT)],
+        ));
+        assert!(matches!(problems.as_slice(), [ConfigError::MissingKey(key)] if key == "APIBARA_KEY"));
+
+        let config = load(
+            &["--network", "mainnet", "--stream-url", "https://proxy.example.com"],
+            &[("CONTRACT_ADDRESS", VAULT), ("APIBARA_KEY", "key")],
+        )
+        .unwrap();
+        assert_eq!(config.network, NetworkName::Mainnet);
+        assert_eq!(config.stream_uri().unwrap().host(), Some("proxy.example.com"));
+
+        let config = load(
+            &[],
+            &[
+                ("CONTRACT_ADDRESS", VAULT),
+                ("SOURCE", "rpc"),
+                ("RPC_URL", "http://127.0.0.1:5050"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.source.kind, SourceKind::Rpc);
+        assert!(config.apibara_key.is_empty());
+    }
+
+    #[test]
+    fn the_rpc_source_defaults_to_accepted_and_rejects_pending() {
+        let vars = [
+            ("CONTRACT_ADDRESS", VAULT),
+            ("SOURCE", "rpc"),
+            ("RPC_URL", "http://127.0.0.1:5050"),
+        ];
+        assert_eq!(load(&[], &vars).unwrap().finality, Finality::Accepted);
+        assert_eq!(
+            load(&["--finality", "finalized"], &vars).unwrap().finality,
+            Finality::Finalized
+        );
+
+        let problems = problems(load(&["--finality", "pending"], &vars));
+        assert!(matches!(problems.as_slice(), [ConfigError::InvalidValue { key, .. }] if key == "FINALITY"));
+    }
+
+    #[test]
+    fn ending_block_before_starting_block_is_rejected() {
+        let problems = problems(load(
+            &["--starting-block", "100", "--ending-block", "99"],
+            &[("CONTRACT_ADDRESS", VAULT), ("APIBARA_KEY", "key")],
+        ));
+        assert!(matches!(problems.as_slice(), [ConfigError::InvalidValue { key, .. }] if key == "ENDING_BLOCK"));
+
+        let config = load(
+            &["--starting-block", "100", "--ending-block", "100"],
+            &[("CONTRACT_ADDRESS", VAULT), ("APIBARA_KEY", "key")],
+        )
+        .unwrap();
+        assert_eq!(config.ending_block, Some(100));
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let problems = problems(load(
+            &[],
+            &[("STARTING_BLOCK", "soon"), ("CHANNEL_CAPACITY", "0"), ("FINALITY", "eventually")],
+        ));
+
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::MissingKey(key) if key == "APIBARA_KEY")));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::MissingKey(key) if key == "CONTRACT_ADDRESS")));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::InvalidNumber { key, .. } if key == "STARTING_BLOCK")));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::InvalidValue { key, .. } if key == "CHANNEL_CAPACITY")));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::InvalidValue { key, .. } if key == "FINALITY")));
+    }
+
+    #[test]
+    fn retry_attempts_are_parsed_as_u32() {
+        let vars = [("APIBARA_KEY", "key"), ("CONTRACT_ADDRESS", VAULT)];
+
+        let config = load(&[], &[vars[0], vars[1], ("RETRY_MAX_ATTEMPTS", "7")]).unwrap();
+        assert_eq!(config.retry.max_retries, Some(7));
+
+        let config = load(&[], &[vars[0], vars[1], ("RETRY_MAX_ATTEMPTS", "0")]).unwrap();
+        assert_eq!(config.retry.max_retries, None);
+
+        let problems = problems(load(&[], &[vars[0], vars[1], ("RETRY_MAX_ATTEMPTS", "4294967296")]));
+        assert!(
+            matches!(problems.as_slice(), [ConfigError::InvalidNumber { key, .. }] if key == "RETRY_MAX_ATTEMPTS"),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_storage_is_reported_with_the_other_problems() {
+        let env = [("SINK", "storage"), ("REDIS_URL", "redis://127.0.0.1:1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let problems = problems(Config::load(["kanshi"], &env).await);
+
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::UnreachableStorage { url, .. } if url == "redis://127.0.0.1:1")));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, ConfigError::MissingKey(key) if key == "CONTRACT_ADDRESS")));
+    }
+
+    #[test]
+    fn configurations_built_in_code_are_validated() {
+        let config = Config {
+            apibara_key: "key".to_string(),
+            contracts: vec![ContractConfig::from_str(VAULT).unwrap()],
+            ..Config::default()
+        };
+        config.validate().unwrap();
+
+        let config = Config {
+            channel_capacity: 0,
+            retry: RetryConfig {
+                multiplier: 0.5,
+                ..RetryConfig::default()
+            },
+            ..config
+        };
+        let problems = problems(config.validate().map(|_| config.clone()));
+        assert!(matches!(
+            problems.as_slice(),
+            [ConfigError::InvalidValue { key: capacity, .. }, ConfigError::InvalidValue { key: multiplier, .. }]
+                if capacity == "CHANNEL_CAPACITY" && multiplier == "RETRY_MULTIPLIER"
+        ));
+    }
+
+    #[test]
+    fn abis_are_loaded_during_validation() {
+        let path = write_file("invalid-abi.json", "{\"abi\": 42}");
+        let abi = format!("{}={}", VAULT, path.display());
+
+        let problems = problems(load(
+            &["--abi", &abi],
+            &[("APIBARA_KEY", "key"), ("CONTRACT_ADDRESS", VAULT)],
+        ));
+        assert!(matches!(problems.as_slice(), [ConfigError::InvalidValue { key, .. }] if key == "ABI_PATHS"));
+
+        std::fs::remove_file(path).unwrap();
+
+        // Valid ones are kept for the indexer
+        let abi = format!(
+            "vault={}/tests/fixtures/erc20_ownable.contract_class.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let contracts = format!("vault={}", VAULT);
+        let config = load(
+            &["--abi", &abi],
+            &[("APIBARA_KEY", "key"), ("CONTRACT_ADDRESS", contracts.as_str())],
+        )
+        .unwrap();
+        assert!(config.contracts[0].abi.is_some());
+    }
+
+    #[test]
+    fn event_filters_are_split_on_semicolons_and_keys_on_commas() {
+        let contracts = format!("vault={},{}", VAULT, POOL);
+        let config = load(
+            &[],
+            &[
+                ("APIBARA_KEY", "key"),
+                ("CONTRACT_ADDRESS", contracts.as_str()),
+                ("EVENT_FILTERS", "Transfer:0x1,0x2; vault/Approval"),
+            ],
+        )
+        .unwrap();
+
+        let transfer = get_selector_from_name("Transfer").unwrap();
+        let approval = get_selector_from_name("Approval").unwrap();
+        let vault = &config.contracts[0];
+        assert_eq!(vault.name, "vault");
+        assert_eq!(
+            vault.events.iter().map(|e| e.filter_keys()).collect::<Vec<_>>(),
+            vec![
+                vec![transfer, Felt::from(1u8), Felt::from(2u8)],
+                vec![approval],
+            ]
+        );
+        let pool = &config.contracts[1];
+        assert_eq!(pool.events.len(), 1);
+        assert_eq!(pool.events[0].filter_keys(), vec![transfer, Felt::from(1u8), Felt::from(2u8)]);
+
+        let problems = problems(load(
+            &[],
+            &[
+                ("APIBARA_KEY", "key"),
+                ("CONTRACT_ADDRESS", VAULT),
+                ("EVENT_FILTERS", "pool/Transfer"),
+            ],
+        ));
+        assert!(matches!(problems.as_slice(), [ConfigError::InvalidFilter { filter, .. }] if filter == "Transfer"));
     }
 }
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::config::Finality;
+
+/// Sent after the last message of a block. Consumers acknowledge it with
+/// `AckHandle::ack` once everything they received for the block is
+/// processed, the indexer only checkpoints acknowledged blocks.
+#[derive(Debug, Clone)]
+pub struct BlockEnd {
+    pub block_number: u64,
+    pub finality: Finality,
+    /// Increases with every block sent, so acknowledgments of blocks that
+    /// were rolled back and streamed again are never confused.
+    pub(crate) sequence: u64,
+}
+
+/// Lets consumers tell the indexer which blocks are fully processed.
+#[derive(Debug, Clone)]
+pub struct AckHandle {
+    acked: Arc<watch::Sender<u64>>,
+}
+
+impl AckHandle {
+    /// Returns the handle and the receiver the indexer watches.
+    pub(crate) fn channel() -> (Self, watch::Receiver<u64>) {
+        let (tx, rx) = watch::channel(0);
+        (AckHandle { acked: Arc::new(tx) }, rx)
+    }
+
+    /// Acknowledges `block` and every block sent before it.
+    pub fn ack(&self, block: &BlockEnd) {
+        self.acked.send_if_modified(|acked| {
+            if block.sequence > *acked {
+                *acked = block.sequence;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
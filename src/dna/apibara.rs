@@ -0,0 +1,213 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use apibara_core::starknet::v1alpha2::{Block, BlockHeader, EventFilter, EventWithTransaction, Filter, HeaderFilter};
+use apibara_sdk::{configuration, ClientBuilder, Configuration, DataMessage};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+
+use crate::config::{Config, Finality};
+use crate::dna::block::{BlockData, StreamMessage};
+use crate::dna::source::DataSource;
+use crate::dna::state::ResumePoint;
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::transaction::{ReceiptData, TransactionData};
+use crate::dna::EventData;
+use crate::utils::conversions::{felt_as_apibara_field, field_to_hex_string, optional_field_to_hex_string};
+
+const INDEXING_STREAM_CHUNK_SIZE: usize = 32;
+
+pub fn build_stream_config(config: &Config, resume: &ResumePoint) -> Configuration<Filter> {
+    let configuration = match resume {
+        ResumePoint::Block(block_number) => Configuration::<Filter>::default().with_starting_block(*block_number),
+        ResumePoint::Cursor(cursor) => Configuration::<Filter>::default().with_starting_cursor(cursor.clone()),
+    };
+
+    configuration
+        .with_finality(config.finality.into())
+        .with_filter(|mut filter| {
+            filter.with_header(HeaderFilter::weak());
+            for contract in &config.contracts {
+                let address = felt_as_apibara_field(&contract.address);
+                // Receipts are always requested, they carry the transaction
+                // hash and index of every event
+                let includes = |event: EventFilter| {
+                    event
+                        .with_include_transaction(config.include_transactions)
+                        .with_include_receipt(true)
+                };
+                if contract.events.is_empty() {
+                    filter.add_event(|event| includes(event.with_from_address(address.clone())));
+                }
+                for event_filter in &contract.events {
+                    let keys = event_filter
+                        .filter_keys()
+                        .iter()
+                        .map(felt_as_apibara_field)
+                        .collect::<Vec<_>>();
+                    filter.add_event(|event| {
+                        includes(
+                            event
+                                .with_from_address(address.clone())
+                                .with_keys(keys.clone()),
+                        )
+                    });
+                }
+            }
+            if config.include_state_updates {
+                // Declared classes are not tied to an address, they are left
+                // out rather than streaming every declaration of the network
+                filter.with_state_update(|state_update| {
+                    config.contracts.iter().fold(state_update, |state_update, contract| {
+                        let address = felt_as_apibara_field(&contract.address);
+                        state_update
+                            .add_storage_diff(|diff| diff.with_contract_address(address.clone()))
+                            .add_deployed_contract(|deployed| deployed.with_contract_address(address.clone()))
+                            .add_nonce_update(|nonce| nonce.with_contract_address(address.clone()))
+                    })
+                });
+            }
+            filter.build()
+        })
+}
+
+/// The Apibara DNA stream of the configured network.
+pub struct ApibaraSource {
+    config: Config,
+}
+
+impl ApibaraSource {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl DataSource for ApibaraSource {
+    async fn start(&self, resume: &ResumePoint) -> Result<BoxStream<'static, Result<StreamMessage>>> {
+        let config = &self.config;
+        let (config_client, config_stream) = configuration::channel(INDEXING_STREAM_CHUNK_SIZE);
+
+        // Initialize the stream with configuration
+        config_client.send(build_stream_config(config, resume)).await?;
+
+        let stream = ClientBuilder::default()
+            .with_bearer_token(Some(config.apibara_key.clone()).filter(|key| !key.is_empty()))
+            .connect(config.stream_uri()?)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to Apibara: {:?}", e))?
+            .start_stream::<Filter, Block, _>(config_stream)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to start stream: {:?}", e))?;
+
+        println!("✅ [Indexer] Connected to Apibara ({}), listening ...", config.network.name());
+
+        let (include_transactions, include_receipts) = (config.include_transactions, config.include_receipts);
+        let messages = stream.map(move |message| {
+            // The stream ends with its configuration client
+            let _ = &config_client;
+            message
+                .map(|message| stream_message(message, include_transactions, include_receipts))
+                .map_err(|e| anyhow::anyhow!("Error while streaming: {:?}", e))
+        });
+        // The server closing the stream is a failure, the indexer reconnects
+        let closed = futures::stream::once(async { Err(anyhow::anyhow!("Stream closed by the server")) });
+        Ok(messages.chain(closed).boxed())
+    }
+}
+
+fn stream_message(message: DataMessage<Block>, include_transactions: bool, include_receipts: bool) -> StreamMessage {
+    match message {
+        DataMessage::Data {
+            cursor: _,
+            end_cursor,
+            finality,
+            batch,
+        } => {
+            let finality = Finality::from(finality);
+            StreamMessage::Data {
+                end_block: end_cursor.order_key,
+                finality,
+                blocks: batch
+                    .into_iter()
+                    .map(|block| block_data(block, finality, include_transactions, include_receipts))
+                    .collect(),
+            }
+        }
+        DataMessage::Invalidate { cursor } => StreamMessage::Invalidate { cursor },
+        DataMessage::Heartbeat => StreamMessage::Heartbeat,
+    }
+}
+
+fn block_data(block: Block, finality: Finality, include_transactions: bool, include_receipts: bool) -> BlockData {
+    let Block { header, events, state_update, .. } = block;
+    let header = header.as_ref();
+
+    BlockData {
+        block_number: header.map(|hdr| hdr.block_number).unwrap_or(0),
+        block_hash: optional_field_to_hex_string(header.and_then(|hdr| hdr.block_hash.as_ref())),
+        events: events
+            .into_iter()
+            .filter_map(|event| event_data(header, event, finality, include_transactions, include_receipts))
+            .collect(),
+        state_update: state_update
+            .as_ref()
+            .and_then(|su| StateUpdateData::new(header, su, finality)),
+    }
+}
+
+/// Flattens an event with the header of its block and the transaction
+/// that emitted it. The contract name and decoded fields are left to the
+/// indexer.
+fn event_data(
+    header: Option<&BlockHeader>,
+    event: EventWithTransaction,
+    finality: Finality,
+    include_transactions: bool,
+    include_receipts: bool,
+) -> Option<EventData> {
+    let transaction_hash = event
+        .receipt
+        .as_ref()
+        .and_then(|receipt| receipt.transaction_hash.as_ref())
+        .or_else(|| {
+            event
+                .transaction
+                .as_ref()
+                .and_then(|tx| tx.meta.as_ref())
+                .and_then(|meta| meta.hash.as_ref())
+        });
+    let transaction_index = event
+        .receipt
+        .as_ref()
+        .map(|receipt| receipt.transaction_index)
+        .unwrap_or_default();
+    let raw = event.event?;
+
+    Some(EventData {
+        block_number: header.map(|hdr| hdr.block_number).unwrap_or(0),
+        block_hash: optional_field_to_hex_string(header.and_then(|hdr| hdr.block_hash.as_ref())),
+        timestamp: header
+            .and_then(|hdr| hdr.timestamp.as_ref())
+            .map(|ts| ts.seconds.max(0) as u64)
+            .unwrap_or_default(),
+        transaction_hash: optional_field_to_hex_string(transaction_hash),
+        transaction_index,
+        event_index: raw.index,
+        from_address: optional_field_to_hex_string(raw.from_address.as_ref()),
+        contract: String::new(),
+        keys: raw.keys.iter().map(field_to_hex_string).collect(),
+        data: raw.data.iter().map(field_to_hex_string).collect(),
+        finality,
+        transaction: event
+            .transaction
+            .as_ref()
+            .filter(|_| include_transactions)
+            .map(TransactionData::from),
+        receipt: event
+            .receipt
+            .as_ref()
+            .filter(|_| include_receipts)
+            .map(ReceiptData::from),
+        decoded: None,
+    })
+}
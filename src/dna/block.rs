@@ -0,0 +1,49 @@
+use apibara_core::node::v1alpha2::Cursor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::Finality;
+use crate::dna::state::SavedCursor;
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::EventData;
+
+/// The data of a block a source matched, whatever it was read from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockData {
+    pub block_number: u64,
+    /// Hex encoded, empty for pending blocks.
+    pub block_hash: String,
+    pub events: Vec<EventData>,
+    pub state_update: Option<StateUpdateData>,
+}
+
+/// What a source produces, modeled after the Apibara stream messages.
+/// Recordings store one message per line as JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamMessage {
+    /// Blocks up to `end_block` were read, only the ones with matching
+    /// data are in `blocks`.
+    Data {
+        end_block: u64,
+        finality: Finality,
+        blocks: Vec<BlockData>,
+    },
+    /// Blocks after `cursor` are no longer part of the chain, or everything
+    /// streamed so far when `None`.
+    Invalidate {
+        #[serde(serialize_with = "serialize_cursor", deserialize_with = "deserialize_cursor")]
+        cursor: Option<Cursor>,
+    },
+    Heartbeat,
+}
+
+fn serialize_cursor<S: Serializer>(cursor: &Option<Cursor>, serializer: S) -> Result<S::Ok, S::Error> {
+    cursor.as_ref().map(SavedCursor::from).serialize(serializer)
+}
+
+fn deserialize_cursor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Cursor>, D::Error> {
+    Option::<SavedCursor>::deserialize(deserializer)?
+        .map(|cursor| cursor.to_cursor())
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
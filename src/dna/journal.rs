@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+/// Number of blocks kept in the journal behind the most recent one.
+/// Reorgs deeper than this can still be rolled back, but what was recorded
+/// for the pruned blocks is no longer known.
+const JOURNAL_DEPTH: u64 = 128;
+
+/// Keeps track of what was emitted (or written) for each recent block so
+/// it can be undone when part of the chain is invalidated.
+#[derive(Debug, Clone)]
+pub struct BlockJournal<T> {
+    blocks: BTreeMap<u64, Vec<T>>,
+}
+
+impl<T> Default for BlockJournal<T> {
+    fn default() -> Self {
+        Self { blocks: BTreeMap::new() }
+    }
+}
+
+impl<T> BlockJournal<T> {
+    pub fn record(&mut self, block_number: u64, item: T) {
+        self.blocks.entry(block_number).or_default().push(item);
+    }
+
+    /// What is recorded for `block_number`.
+    pub fn get(&self, block_number: u64) -> &[T] {
+        self.blocks.get(&block_number).map_or(&[], Vec::as_slice)
+    }
+
+    /// Forgets `block_number` and returns what was recorded for it, e.g.
+    /// before recording a newer version of the block.
+    pub fn take(&mut self, block_number: u64) -> Vec<T> {
+        self.blocks.remove(&block_number).unwrap_or_default()
+    }
+
+    /// Removes every block after `block_number`, or every block when `None`,
+    /// and returns what was recorded for them.
+    pub fn rollback(&mut self, block_number: Option<u64>) -> Vec<T> {
+        let invalidated = match block_number {
+            Some(block_number) => self.blocks.split_off(&(block_number + 1)),
+            None => std::mem::take(&mut self.blocks),
+        };
+
+        invalidated.into_values().flatten().collect()
+    }
+
+    /// Drops blocks that are too old to be reorganized anymore.
+    pub fn prune(&mut self, latest_block: u64) {
+        let oldest = latest_block.saturating_sub(JOURNAL_DEPTH);
+        self.blocks = self.blocks.split_off(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_returns_what_was_recorded_after_the_block() {
+        let mut journal = BlockJournal::default();
+        journal.record(10, "a");
+        journal.record(11, "b");
+        journal.record(11, "c");
+        journal.record(12, "d");
+
+        assert_eq!(journal.rollback(Some(12)), Vec::<&str>::new());
+        assert_eq!(journal.rollback(Some(10)), vec!["b", "c", "d"]);
+        assert_eq!(journal.rollback(Some(10)), Vec::<&str>::new());
+        assert_eq!(journal.rollback(None), vec!["a"]);
+    }
+
+    #[test]
+    fn a_block_can_be_recorded_again() {
+        let mut journal = BlockJournal::default();
+        journal.record(10, "a");
+        journal.record(11, "b");
+        journal.record(11, "c");
+
+        assert_eq!(journal.take(11), vec!["b", "c"]);
+        journal.record(11, "d");
+        assert_eq!(journal.get(11), ["d"]);
+        assert_eq!(journal.take(12), Vec::<&str>::new());
+        assert_eq!(journal.rollback(None), vec!["a", "d"]);
+    }
+
+    #[test]
+    fn blocks_deeper_than_the_journal_are_pruned() {
+        let mut journal = BlockJournal::default();
+        for block_number in 0..=200 {
+            journal.record(block_number, block_number);
+            journal.prune(block_number);
+        }
+
+        let kept = journal.rollback(None);
+        assert_eq!(kept.len() as u64, JOURNAL_DEPTH + 1);
+        assert_eq!(kept.first(), Some(&(200 - JOURNAL_DEPTH)));
+        assert_eq!(kept.last(), Some(&200));
+    }
+}
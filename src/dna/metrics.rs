@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Backpressure statistics of the channel between the indexer and its
+/// consumers, shared with whoever wants to export them.
+#[derive(Debug, Default)]
+pub struct ChannelMetrics {
+    capacity: AtomicU64,
+    depth: AtomicU64,
+    max_depth: AtomicU64,
+    blocked_sends: AtomicU64,
+    blocked_micros: AtomicU64,
+}
+
+/// Point in time copy of `ChannelMetrics`.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelStats {
+    pub capacity: u64,
+    /// Messages waiting in the channel after the last send.
+    pub depth: u64,
+    pub max_depth: u64,
+    /// Sends that found the channel full and had to wait.
+    pub blocked_sends: u64,
+    /// Total time the stream waited for consumers.
+    pub blocked: Duration,
+}
+
+impl ChannelMetrics {
+    pub fn new(capacity: usize) -> Self {
+        let metrics = ChannelMetrics::default();
+        metrics.capacity.store(capacity as u64, Ordering::Relaxed);
+        metrics
+    }
+
+    /// Records a send that left `depth` messages in the channel, after
+    /// waiting `blocked` for room when it was full.
+    pub(crate) fn record_send(&self, depth: usize, blocked: Option<Duration>) {
+        self.depth.store(depth as u64, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth as u64, Ordering::Relaxed);
+        if let Some(blocked) = blocked {
+            self.blocked_sends.fetch_add(1, Ordering::Relaxed);
+            self.blocked_micros
+                .fetch_add(blocked.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ChannelStats {
+        ChannelStats {
+            capacity: self.capacity.load(Ordering::Relaxed),
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            blocked_sends: self.blocked_sends.load(Ordering::Relaxed),
+            blocked: Duration::from_micros(self.blocked_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl std::fmt::Display for ChannelStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "queue {}/{} (max {}), blocked {} times for {:.1?}",
+            self.depth, self.capacity, self.max_depth, self.blocked_sends, self.blocked
+        )
+    }
+}
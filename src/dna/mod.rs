@@ -1,206 +1,703 @@
-use std::fs;
-use std::future::Future;
-use std::path::PathBuf;
-use std::pin::Pin;
+pub mod ack;
+pub mod apibara;
+pub mod block;
+pub mod journal;
+pub mod metrics;
+pub mod replay;
+pub mod rpc;
+pub mod source;
+pub mod state;
+pub mod state_update;
+pub mod transaction;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, NetworkName};
-use tokio::sync::mpsc;
+use crate::abi::{ContractAbi, DecodedEvent};
+use crate::config::{CheckpointBackend, Config, Finality};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch};
+use crate::services::checkpoint::{CheckpointStore, FileCheckpointStore, StorageCheckpointStore};
 use crate::services::dataStore::StorageManager;
-use crate::utils::conversions::felt_as_apibara_field;
-use anyhow::Result;
-use apibara_core::starknet::v1alpha2::Event;
-use apibara_core::{
-    node::v1alpha2::DataFinality,
-    starknet::v1alpha2::{Block, Filter, HeaderFilter},
-};
-use apibara_sdk::{configuration, ClientBuilder, Configuration, Uri};
+use crate::dna::ack::{AckHandle, BlockEnd};
+use crate::dna::block::StreamMessage;
+use crate::dna::journal::BlockJournal;
+use crate::dna::metrics::{ChannelMetrics, ChannelStats};
+use crate::dna::source::{DataSource, FatalSourceError};
+use crate::dna::state::{BlockState, ResumePoint};
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::transaction::{ReceiptData, TransactionData};
+use crate::utils::backoff::Backoff;
+use crate::utils::conversions::hex_string_to_bytes;
+use anyhow::{Context, Result};
+use starknet::core::types::Felt;
+use apibara_core::node::v1alpha2::{Cursor, DataFinality};
 use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
 
-const INDEXING_STREAM_CHUNK_SIZE: usize = 32;
-
-#[derive(Serialize, Deserialize)]
-struct BlockState {
-    last_processed_block: u64,
-}
-
 #[derive(Clone)]
 pub struct IndexerService {
     config: Config,
-    uri: Uri,
-    stream_config: Configuration<Filter>
+    source: Arc<dyn DataSource>,
+    /// Where the next connection starts streaming from.
+    resume: ResumePoint,
+    /// Events sent for recent blocks, returned on rollback.
+    journal: BlockJournal<EventData>,
+    retries: Arc<AtomicU64>,
+    channel_metrics: Arc<ChannelMetrics>,
+    checkpoints: Arc<dyn CheckpointStore>,
+    contract_names: HashMap<Felt, String>,
+    abis: HashMap<Felt, Arc<ContractAbi>>,
+    /// Cursor of the last block sent to consumers. Reconnections resume
+    /// from it since consumers still hold whatever they didn't process yet.
+    last_cursor: Option<Cursor>,
+    ack_handle: AckHandle,
+    /// Sequence of the last `BlockEnd` acknowledged by consumers.
+    acked: watch::Receiver<u64>,
+    /// Cursors of the blocks sent but not acknowledged yet, by sequence.
+    unacked: BTreeMap<u64, Cursor>,
+    next_sequence: u64,
+    /// Block of the last persisted checkpoint.
+    checkpointed_block: Option<u64>,
+    /// When the last checkpoint was written.
+    checkpointed_at: Option<Instant>,
+    /// When acknowledged blocks are checkpointed next, set while some wait.
+    checkpoint_due: Option<Instant>,
+    shutdown: watch::Receiver<bool>,
+    blocks_processed: u64,
+    events_emitted: u64,
+    started_at: Instant,
+    /// Blocks emitted with pending finality and not yet seen as accepted.
+    pending_blocks: BTreeSet<u64>,
+}
+
+/// Acknowledgements are coalesced into at most one checkpoint write per
+/// interval.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What woke up the streaming loop.
+enum Wakeup<T> {
+    Stream(T),
+    Acked,
+    CheckpointDue,
+    Shutdown,
+}
+
+/// Messages sent by the indexer to its consumers.
+#[derive(Debug, Clone)]
+pub enum IndexerMessage {
+    /// An event emitted by one of the configured contracts.
+    Event(EventData),
+    /// State changes of a block, sent after its events when
+    /// `include_state_updates` is enabled.
+    StateUpdate(StateUpdateData),
+    /// `block_number`, previously streamed as pending, reached `finality`.
+    /// The pending events of that block are superseded by the events that
+    /// follow with the new finality, if any.
+    Promoted { block_number: u64, finality: Finality },
+    /// The chain was reorganized: everything after `block_number`, or
+    /// everything streamed when `None`, is no longer valid and `invalidated`
+    /// lists the events that were undone.
+    Rollback { block_number: Option<u64>, invalidated: Vec<EventData> },
+    /// Every message of a block was sent. The block is only checkpointed
+    /// once consumers acknowledge it with the `AckHandle`.
+    BlockEnd(BlockEnd),
+    /// The configured `ending_block` was processed, nothing else is sent.
+    BackfillComplete(BackfillSummary),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// What a bounded run processed.
+#[derive(Debug, Clone)]
+pub struct BackfillSummary {
+    pub starting_block: u64,
+    pub ending_block: u64,
+    pub blocks_processed: u64,
+    pub events_emitted: u64,
+    pub retries: u64,
+    pub elapsed: Duration,
+    pub channel: ChannelStats,
+}
+
+impl std::fmt::Display for BackfillSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "blocks {}..={}: {} blocks with events, {} events, {} reconnections in {:.1?}, {}",
+            self.starting_block,
+            self.ending_block,
+            self.blocks_processed,
+            self.events_emitted,
+            self.retries,
+            self.elapsed,
+            self.channel
+        )
+    }
+}
+
+/// An event with the block and transaction it was emitted in. Field
+/// elements are hex encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventData {
     pub block_number: u64,
-    pub from_address: String,
+    pub block_hash: String,
+    /// Block timestamp, in seconds since the epoch.
     pub timestamp: u64,
     pub transaction_hash: String,
+    pub transaction_index: u64,
+    /// Position of the event within its block.
+    pub event_index: u64,
+    pub from_address: String,
+    /// Name of the configured contract that emitted the event.
+    pub contract: String,
+    pub keys: Vec<String>,
     pub data: Vec<String>,
+    pub finality: Finality,
+    /// Set when `include_transactions` is enabled.
+    pub transaction: Option<TransactionData>,
+    /// Set when `include_receipts` is enabled.
+    pub receipt: Option<ReceiptData>,
+    /// Set when the contract has an ABI configured.
+    pub decoded: Option<DecodedEvent>,
+}
+
+impl From<Finality> for DataFinality {
+    fn from(finality: Finality) -> Self {
+        match finality {
+            Finality::Pending => DataFinality::DataStatusPending,
+            Finality::Accepted => DataFinality::DataStatusAccepted,
+            Finality::Finalized => DataFinality::DataStatusFinalized,
+        }
+    }
+}
+
+impl From<DataFinality> for Finality {
+    fn from(finality: DataFinality) -> Self {
+        match finality {
+            DataFinality::DataStatusFinalized => Finality::Finalized,
+            DataFinality::DataStatusAccepted => Finality::Accepted,
+            _ => Finality::Pending,
+        }
+    }
 }
 
 impl IndexerService {
-    async fn save_block_state(&self, block_number: u64) -> Result<()> {
-        let state = BlockState {
-            last_processed_block: block_number,
-        };
-        
-        let state_path = self.get_state_file_path();
-        let state_json = serde_json::to_string(&state)?;
-        fs::write(state_path, state_json)?;
-        
-        Ok(())
+    async fn save_block_state(&self, cursor: &Cursor) -> Result<()> {
+        if !self.persists_state() {
+            return Ok(());
+        }
+
+        let state = BlockState::new(cursor, self.config.fingerprint());
+        self.checkpoints.save(&serde_json::to_value(&state)?).await
     }
 
-    fn load_block_state(&self) -> Result<Option<u64>> {
-        let state_path = self.get_state_file_path();
-        
-        if !state_path.exists() {
-            return Ok(None);
+    async fn load_block_state(&self) -> Result<Option<ResumePoint>> {
+        match self.checkpoints.load().await? {
+            Some(state) => Ok(Some(BlockState::parse(state, &self.config.fingerprint())?)),
+            None => Ok(None),
         }
-        
-        let state_json = fs::read_to_string(state_path)?;
-        let state: BlockState = serde_json::from_str(&state_json)?;
-        
-        Ok(Some(state.last_processed_block))
     }
 
-    // Helper to get state file path
-    fn get_state_file_path(&self) -> PathBuf {
-        let config = self.config.clone();
-        PathBuf::from(config.write_path)
+    async fn checkpoint_store(config: &Config) -> Result<Arc<dyn CheckpointStore>> {
+        Ok(match config.checkpoint.backend {
+            CheckpointBackend::File => Arc::new(FileCheckpointStore::new(&config.write_path)),
+            CheckpointBackend::Storage => {
+                let storage = StorageManager::new(config)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to connect to checkpoint storage: {}", e))?;
+                Arc::new(StorageCheckpointStore::new(
+                    Arc::new(storage),
+                    &config.checkpoint.indexer_id,
+                ))
+            }
+        })
+    }
+
+    /// Bounded backfills leave the resume state alone unless asked to.
+    fn persists_state(&self) -> bool {
+        self.config.ending_block.is_none() || self.config.persist_backfill_state
+    }
+
+    /// Reads blocks from `source` instead of the one picked by
+    /// `config.source`, e.g. a `ReplaySource` to run the pipeline offline.
+    pub fn with_source(mut self, source: Arc<dyn DataSource>) -> Self {
+        self.source = source;
+        self
     }
 
+    /// Stops streaming once `shutdown` is set to `true`.
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
 
-    pub async fn new(config: Config) -> Self {
-        // First create with default starting block
-        let uri = match config.network {
-            NetworkName::Mainnet => Uri::from_static("https://mainnet.starknet.a5a.ch"),
-            NetworkName::Sepolia => Uri::from_static("https://sepolia.starknet.a5a.ch"),
+    fn shutdown_requested(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Rolls back to `cursor`, the last block the source still considers
+    /// part of the chain.
+    async fn rollback_to(&mut self, cursor: Cursor, tx: &mpsc::Sender<IndexerMessage>) -> Result<bool> {
+        let block_number = cursor.order_key;
+        println!("⚠️ [Indexer] Chain reorganization, rolling back to block {}", block_number);
+
+        // Only rewrite the checkpoint if it points at an invalidated block,
+        // blocks up to the rollback point may not be acknowledged yet
+        if self.checkpointed_block.map_or(false, |b| b > block_number) {
+            self.save_block_state(&cursor).await?;
+            self.checkpointed_block = Some(block_number);
+        }
+        self.last_cursor = Some(cursor);
+
+        Ok(self.undo_after(Some(block_number), tx).await)
+    }
+
+    /// Undoes everything streamed after `block_number`, or everything
+    /// streamed so far when `None`.
+    async fn undo_after(&mut self, block_number: Option<u64>, tx: &mpsc::Sender<IndexerMessage>) -> bool {
+        let is_kept = move |b: u64| block_number.map_or(false, |n| b <= n);
+
+        let invalidated = self.journal.rollback(block_number);
+        self.pending_blocks.retain(|b| is_kept(*b));
+        self.unacked.retain(|_, c| is_kept(c.order_key));
+
+        let message = IndexerMessage::Rollback { block_number, invalidated };
+        self.send(tx, message).await
+    }
+
+    /// Resumes from the last persisted cursor, falling back to the
+    /// configured starting block.
+    async fn resume_from_saved_state(&mut self) -> Result<()> {
+        let saved = match &self.last_cursor {
+            Some(cursor) => Some(ResumePoint::Cursor(cursor.clone())),
+            None if self.persists_state() => {
+                // A checkpoint that can't be read is an error, falling back to the
+                // starting block would silently reindex everything
+                let saved = self.load_block_state().await?;
+                self.checkpointed_block = match &saved {
+                    Some(ResumePoint::Cursor(cursor)) => Some(cursor.order_key),
+                    Some(ResumePoint::Block(block_number)) => Some(*block_number),
+                    None => None,
+                };
+                saved
+            }
+            None => None,
         };
 
+        match &saved {
+            Some(ResumePoint::Cursor(cursor)) => {
+                println!("✅ [Indexer] Resuming after block: {}", cursor.order_key);
+            }
+            Some(ResumePoint::Block(block_number)) => {
+                println!("✅ [Indexer] Loaded last processed block: {}", block_number);
+            }
+            None => {
+                println!("✅ [Indexer] Starting from initial block: {}", self.config.starting_block);
+            }
+        }
+
+        self.resume = saved.unwrap_or(ResumePoint::Block(self.config.starting_block));
+        Ok(())
+    }
+
+    pub async fn new(config: Config) -> Result<Self> {
+        let (ack_handle, acked) = AckHandle::channel();
+
         // Create initial service with config's starting block
         let mut service = IndexerService {
             config: config.clone(),
-            uri,
-            stream_config: Configuration::<Filter>::default()
-                .with_starting_block(config.starting_block)
-                .with_finality(DataFinality::DataStatusPending)
-                .with_filter(|mut filter| {
-                    filter
-                        .with_header(HeaderFilter::weak())
-                        .add_event(|event| {
-                            event.with_from_address(felt_as_apibara_field(&config.contract_address))
-                        })
-                        .build()
-                }),
+            source: source::from_config(&config)?,
+            resume: ResumePoint::Block(config.starting_block),
+            journal: BlockJournal::default(),
+            retries: Arc::new(AtomicU64::new(0)),
+            channel_metrics: Arc::new(ChannelMetrics::new(config.channel_capacity)),
+            checkpoints: Self::checkpoint_store(&config).await?,
+            contract_names: config
+                .contracts
+                .iter()
+                .map(|c| (c.address, c.name.clone()))
+                .collect(),
+            abis: Self::load_abis(&config)?,
+            last_cursor: None,
+            ack_handle,
+            acked,
+            unacked: BTreeMap::new(),
+            next_sequence: 1,
+            checkpointed_block: None,
+            checkpointed_at: None,
+            checkpoint_due: None,
+            // Never signaled unless `with_shutdown` is used
+            shutdown: watch::channel(false).1,
+            blocks_processed: 0,
+            events_emitted: 0,
+            started_at: Instant::now(),
+            pending_blocks: BTreeSet::new(),
+        };
+
+        service.resume_from_saved_state().await?;
+
+        Ok(service)
+    }
+
+    fn load_abis(config: &Config) -> Result<HashMap<Felt, Arc<ContractAbi>>> {
+        config
+            .contracts
+            .iter()
+            .filter_map(|c| match (&c.abi, &c.abi_path) {
+                (Some(abi), _) => Some(Ok((c.address, abi.clone()))),
+                // Configurations built in code may not have loaded it yet
+                (None, Some(path)) => Some(ContractAbi::load(path).map(|abi| (c.address, Arc::new(abi)))),
+                (None, None) => None,
+            })
+            .collect()
+    }
+
+    /// Names the configured contract that emitted `event` and decodes it
+    /// with the contract ABI, if any. Events the ABI can't decode are still
+    /// emitted, undecoded.
+    fn annotate(&self, event: &mut EventData) {
+        let Ok(address) = Felt::from_hex(&event.from_address) else {
+            return;
+        };
+        event.contract = self
+            .contract_names
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| address.to_hex_string());
+
+        let Some(abi) = self.abis.get(&address) else {
+            return;
+        };
+        let felts = |values: &[String]| values.iter().map(|v| Felt::from_hex(v)).collect::<Result<Vec<_>, _>>();
+        let (Ok(keys), Ok(data)) = (felts(&event.keys), felts(&event.data)) else {
+            return;
+        };
+        match abi.decode_event(&keys, &data) {
+            Ok(decoded) => event.decoded = Some(decoded),
+            Err(e) => println!("⚠️ [Warning] Failed to decode event of {}: {:#}", address.to_hex_string(), e),
+        }
+    }
+
+    /// Whether `block_number` is past the configured `ending_block`.
+    fn is_past_end(&self, block_number: u64) -> bool {
+        self.config.ending_block.map_or(false, |end| block_number > end)
+    }
+
+    fn summary(&self) -> BackfillSummary {
+        BackfillSummary {
+            starting_block: self.config.starting_block,
+            ending_block: self.config.ending_block.unwrap_or_default(),
+            blocks_processed: self.blocks_processed,
+            events_emitted: self.events_emitted,
+            retries: self.retry_count(),
+            elapsed: self.started_at.elapsed(),
+            channel: self.channel_metrics.snapshot(),
+        }
+    }
+
+    /// Notifies consumers that pending blocks up to `block_number` reached
+    /// `finality`. Returns `false` when the receiver is gone.
+    async fn promote_pending(&mut self, block_number: u64, finality: Finality, tx: &mpsc::Sender<IndexerMessage>) -> bool {
+        let still_pending = self.pending_blocks.split_off(&(block_number + 1));
+        let promoted = std::mem::replace(&mut self.pending_blocks, still_pending);
+
+        for block_number in promoted {
+            if !self.send(tx, IndexerMessage::Promoted { block_number, finality }).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sends `message`, waiting for room in the channel when consumers lag
+    /// behind so the stream is throttled. Returns `false` when the receiver
+    /// is gone.
+    async fn send(&self, tx: &mpsc::Sender<IndexerMessage>, message: IndexerMessage) -> bool {
+        let blocked = match tx.try_send(message) {
+            Ok(()) => None,
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(message)) => {
+                let started = Instant::now();
+                if tx.send(message).await.is_err() {
+                    return false;
+                }
+                Some(started.elapsed())
+            }
+        };
+        self.channel_metrics
+            .record_send(tx.max_capacity() - tx.capacity(), blocked);
+        true
+    }
+
+    /// Queue depth and time spent waiting on consumers.
+    pub fn channel_metrics(&self) -> Arc<ChannelMetrics> {
+        self.channel_metrics.clone()
+    }
+
+    /// Handle consumers use to acknowledge the `BlockEnd` messages they
+    /// processed.
+    pub fn ack_handle(&self) -> AckHandle {
+        self.ack_handle.clone()
+    }
+
+    /// Checkpoints the last block acknowledged by consumers.
+    async fn persist_acknowledged(&mut self) -> Result<()> {
+        self.checkpoint_due = None;
+        let acked = *self.acked.borrow_and_update();
+        let Some(cursor) = self.unacked.range(..=acked).next_back().map(|(_, c)| c.clone()) else {
+            return Ok(());
         };
 
-        // Try to load saved block state
-        if let Ok(Some(block_number)) = service.load_block_state() {
-            // Update stream_config with loaded block number
-            service.stream_config = Configuration::<Filter>::default()
-                .with_starting_block(block_number)
-                .with_finality(DataFinality::DataStatusPending)
-                .with_filter(|mut filter| {
-                    filter
-                        .with_header(HeaderFilter::weak())
-                        .add_event(|event| {
-                            event.with_from_address(felt_as_apibara_field(&config.contract_address))
-                        })
-                        .build()
-                });
-            println!("✅ [Indexer] Loaded last processed block: {}", block_number);
-        } else {
-            println!("✅ [Indexer] Starting from initial block: {}", config.starting_block);
+        if let Err(e) = self.save_block_state(&cursor).await {
+            // The acknowledged blocks stay pending, the save is retried one
+            // interval later
+            self.checkpoint_due = Some(Instant::now() + CHECKPOINT_INTERVAL);
+            return Err(e);
         }
+        self.unacked = self.unacked.split_off(&(acked + 1));
+        self.checkpointed_at = Some(Instant::now());
+        self.checkpointed_block = Some(cursor.order_key);
+        Ok(())
+    }
 
-        service
+    /// Schedules a checkpoint of the acknowledged blocks, one interval after
+    /// the previous one so a burst of acknowledgements is written once.
+    fn schedule_checkpoint(&mut self) {
+        let due = self
+            .checkpointed_at
+            .map_or_else(Instant::now, |at| at + CHECKPOINT_INTERVAL);
+        self.checkpoint_due.get_or_insert(due);
     }
 
-    pub async fn run_forever_simplified(&mut self, tx: &mpsc::UnboundedSender<Event>) -> Result<()> {
+    /// Waits until consumers acknowledged every block sent, or are gone, then
+    /// checkpoints the last acknowledged block before stopping.
+    async fn wait_for_acks(&mut self, tx: &mpsc::Sender<IndexerMessage>) -> Result<()> {
+        while let Some(&last) = self.unacked.keys().next_back() {
+            if *self.acked.borrow() >= last {
+                break;
+            }
+            tokio::select! {
+                changed = self.acked.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                _ = tx.closed() => break,
+            }
+        }
+        self.persist_acknowledged().await
+    }
+
+    /// Total number of times the stream had to be reconnected.
+    pub fn retry_count(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Streams events into `tx`, reconnecting with exponential backoff
+    /// whenever the connection to the source fails.
+    pub async fn run_forever_simplified(&mut self, tx: &mpsc::Sender<IndexerMessage>) -> Result<()> {
         println!("✅ [Indexer] Starting event listener...");
+        let mut backoff = Backoff::new(self.config.retry.clone());
+
+        loop {
+            let error = match self.stream_once(tx, &mut backoff).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            // Reconnecting would fail the same way
+            if error.chain().any(|cause| cause.is::<FatalSourceError>()) {
+                return Err(error);
+            }
+
+            let Some(delay) = backoff.next_delay() else {
+                return Err(error.context(format!(
+                    "Giving up after {} consecutive reconnection attempts",
+                    backoff.attempt()
+                )));
+            };
+            let retries = self.retries.fetch_add(1, Ordering::Relaxed) + 1;
+            println!(
+                "⚠️ [Indexer] {:#}, reconnecting in {:?} (retry #{})",
+                error, delay, retries
+            );
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                Ok(_) = self.shutdown.wait_for(|stop| *stop) => {}
+            }
+            if self.shutdown_requested() {
+                return Ok(());
+            }
+            self.resume_from_saved_state().await?;
+        }
+    }
+
+    /// Runs a single connection to the source until it fails. Returns `Ok`
+    /// only when consumers are gone, a shutdown was requested or the source
+    /// ended, and the indexer should stop.
+    async fn stream_once(&mut self, tx: &mpsc::Sender<IndexerMessage>, backoff: &mut Backoff) -> Result<()> {
         let mut reached_pending_block: bool = false;
-        let (config_client, config_stream) = configuration::channel(INDEXING_STREAM_CHUNK_SIZE);
-        
-        // Initialize the stream with configuration
-        config_client.send(self.stream_config.clone()).await?;
-
-        let mut stream = ClientBuilder::default()
-            .with_bearer_token(Some(self.config.apibara_key.clone()))
-            .connect(self.uri.clone())
-            .await
-            .unwrap()
-            .start_stream::<Filter, Block, _>(config_stream)
-            .await
-            .unwrap();
-        
-        println!("✅ [Indexer] Connected to Apibara, listening ...");
+        let mut stream = self.source.start(&self.resume).await?;
 
         loop {
-            match stream.try_next().await {
+            let checkpoint_due = self.checkpoint_due;
+            let next = tokio::select! {
+                next = stream.try_next() => Wakeup::Stream(next),
+                Ok(()) = self.acked.changed() => Wakeup::Acked,
+                _ = tokio::time::sleep_until(checkpoint_due.unwrap_or_else(Instant::now).into()),
+                    if checkpoint_due.is_some() => Wakeup::CheckpointDue,
+                Ok(_) = self.shutdown.wait_for(|stop| *stop) => Wakeup::Shutdown,
+            };
+            let next = match next {
+                Wakeup::Stream(next) => next,
+                Wakeup::Acked => {
+                    self.schedule_checkpoint();
+                    continue;
+                }
+                Wakeup::CheckpointDue => {
+                    if let Err(e) = self.persist_acknowledged().await {
+                        println!("⚠️ [Warning] Failed to save block state: {:?}", e);
+                    }
+                    continue;
+                }
+                Wakeup::Shutdown => {
+                    println!("✅ [Indexer] Shutdown requested, waiting for consumers to finish...");
+                    // Consumers drain the channel, checkpoint the last block they acknowledge
+                    self.wait_for_acks(tx).await?;
+                    if let Some(block_number) = self.checkpointed_block {
+                        println!("✅ [Indexer] Stopped after block {}", block_number);
+                    }
+                    return Ok(());
+                }
+            };
+
+            match next {
                 Ok(Some(response)) => {
+                    backoff.reset();
                     match response {
-                        apibara_sdk::DataMessage::Data {
-                            cursor: _,
-                            end_cursor: _,
+                        StreamMessage::Data {
+                            end_block,
                             finality,
-                            batch,
+                            blocks,
                         } => {
-                            if finality == DataFinality::DataStatusPending && !reached_pending_block {
+                            if finality == Finality::Pending && !reached_pending_block {
                                 println!("[🔍 Indexer] 🥳🎉 Reached pending block!");
                                 reached_pending_block = true;
                             }
                             
-                            for block in batch {
-                                let block_number = block.header.as_ref()
-                                    .map(|hdr| hdr.block_number)
-                                    .unwrap_or(0);
-                                for event in block.events {
-                                    if let Some(event) = event.event {
-                                        let block_number = block.header.as_ref()
-                                            .map(|hdr| hdr.block_number)
-                                            .unwrap_or(0);
-                                        
-                                        println!("\n\n📦 [APIBARA EVENT RECEIVED] Block: {}\n\n", block_number);
-        
-                                        if tx.send(event).is_err() {
-                                            println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
-                                            return Ok(());
-                                        }
+                            for block in blocks {
+                                let block_number = block.block_number;
+                                if self.is_past_end(block_number) {
+                                    break;
+                                }
+                                if finality == Finality::Pending {
+                                    self.pending_blocks.insert(block_number);
+                                } else if !self.promote_pending(block_number, finality, tx).await {
+                                    println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                    return Ok(());
+                                }
+                                self.blocks_processed += 1;
+                                // A block streamed again, as a pending update or once accepted,
+                                // supersedes what was recorded for its previous version
+                                self.journal.take(block_number);
+                                for mut event in block.events {
+                                    self.annotate(&mut event);
+                                    println!("\n\n📦 [EVENT RECEIVED] Block: {}\n\n", block_number);
+
+                                    self.journal.record(block_number, event.clone());
+
+                                    if !self.send(tx, IndexerMessage::Event(event)).await {
+                                        println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                        return Ok(());
+                                    }
+                                    self.events_emitted += 1;
+                                }
+                                if let Some(update) = block.state_update {
+                                    if !self.send(tx, IndexerMessage::StateUpdate(update)).await {
+                                        println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                        return Ok(());
+                                    }
+                                }
+                                let sequence = self.next_sequence;
+                                self.next_sequence += 1;
+                                // Pending blocks have no hash yet and may still change,
+                                // only checkpoint them once accepted
+                                if finality != Finality::Pending {
+                                    let unique_key = hex_string_to_bytes(&block.block_hash)
+                                        .with_context(|| format!("Block {} has a malformed hash", block_number))?;
+                                    if unique_key.is_empty() {
+                                        anyhow::bail!("Block {} was streamed without its hash", block_number);
                                     }
+                                    let cursor = Cursor { order_key: block_number, unique_key };
+                                    self.unacked.insert(sequence, cursor.clone());
+                                    self.last_cursor = Some(cursor);
+                                }
+                                self.journal.prune(block_number);
+
+                                let block_end = BlockEnd { block_number, finality, sequence };
+                                if !self.send(tx, IndexerMessage::BlockEnd(block_end)).await {
+                                    println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                    return Ok(());
                                 }
-                                if let Err(e) = self.save_block_state(block_number).await {
-                                    println!("⚠️ [Warning] Failed to save block state: {:?}", e);
+                            }
+
+                            // Accepted blocks whose events disappeared are not sent again
+                            if finality != Finality::Pending
+                                && !self.promote_pending(end_block, finality, tx).await
+                            {
+                                println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                return Ok(());
+                            }
+
+                            // Blocks without matching events are not sent, rely on the
+                            // batch end block to know the range is covered
+                            if let Some(end) = self.config.ending_block {
+                                if end_block >= end {
+                                    if self.persists_state() {
+                                        self.wait_for_acks(tx).await?;
+                                    }
+                                    let summary = self.summary();
+                                    println!("✅ [Indexer] Backfill complete, {}", summary);
+                                    self.send(tx, IndexerMessage::BackfillComplete(summary)).await;
+                                    return Ok(());
                                 }
                             }
                         }
-                        apibara_sdk::DataMessage::Invalidate { cursor } => {
-                            if let Some(c) = cursor {
-                                return Err(anyhow::anyhow!(
-                                    "Received an invalidate request data at {}",
-                                    &c.order_key
-                                ));
+                        StreamMessage::Invalidate { cursor: Some(cursor) } => {
+                            if !self.rollback_to(cursor, tx).await? {
+                                println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                return Ok(());
                             }
-                            return Err(anyhow::anyhow!("Invalidate request without cursor provided"));
                         }
-                        apibara_sdk::DataMessage::Heartbeat => {
-                            println!("❤️ Heartbeat received");
+                        StreamMessage::Invalidate { cursor: None } => {
+                            // Sources always say where the chain forked, without a cursor
+                            // nothing after the checkpoint can be trusted. Undo it and
+                            // reconnect from the checkpoint rather than clearing it.
+                            let checkpoint = self.checkpointed_block.filter(|_| self.persists_state());
+                            if !self.undo_after(checkpoint, tx).await {
+                                println!("⚠️ [Warning] Receiver dropped, stopping indexer...");
+                                return Ok(());
+                            }
+                            self.last_cursor = None;
+                            anyhow::bail!("The source invalidated the stream without a cursor");
+                        }
+                        StreamMessage::Heartbeat => {
+                            println!("❤️ Heartbeat received, {}", self.channel_metrics.snapshot());
                         }
                     }
                 },
                 Ok(None) => {
-                    println!("Stream ended");
-                    break;
+                    // Only sources with an end stop on their own, e.g. a replay
+                    println!("✅ [Indexer] Source exhausted, waiting for consumers to finish...");
+                    self.wait_for_acks(tx).await?;
+                    return Ok(());
                 },
                 Err(e) => {
-                    return Err(anyhow::anyhow!("Error while streaming: {:?}", e));
+                    return Err(e);
                 }
             }
         }
-        Ok(())
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::future;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::LinesStream;
+
+use crate::dna::block::StreamMessage;
+use crate::dna::source::DataSource;
+use crate::dna::state::ResumePoint;
+
+/// Replays messages recorded by `RecordingSource`, one JSON message per
+/// line, then ends. Messages before the resume point are skipped so a
+/// replay picks up where the checkpoint left it.
+pub struct ReplaySource {
+    path: PathBuf,
+}
+
+impl ReplaySource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl DataSource for ReplaySource {
+    async fn start(&self, resume: &ResumePoint) -> Result<BoxStream<'static, Result<StreamMessage>>> {
+        let file = File::open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open recording {}", self.path.display()))?;
+        let first_block = match resume {
+            ResumePoint::Block(block_number) => *block_number,
+            ResumePoint::Cursor(cursor) => cursor.order_key + 1,
+        };
+        println!(
+            "✅ [Indexer] Replaying {} from block {}",
+            self.path.display(),
+            first_block
+        );
+
+        let path = self.path.display().to_string();
+        let mut skipping = true;
+        let mut skip_handled = move |message: Result<StreamMessage>| {
+            let mut message = match message {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e)),
+            };
+            if skipping {
+                // Whatever precedes the first block to stream was
+                // already handled before the checkpoint
+                match &mut message {
+                    StreamMessage::Data { end_block, blocks, .. } if *end_block >= first_block => {
+                        blocks.retain(|block| block.block_number >= first_block);
+                        skipping = false;
+                    }
+                    _ => return None,
+                }
+            }
+            Some(Ok(message))
+        };
+
+        let messages = LinesStream::new(BufReader::new(file).lines())
+            .enumerate()
+            .filter(|(_, line)| future::ready(line.as_ref().map_or(true, |line| !line.trim().is_empty())))
+            .map(move |(number, line)| {
+                let line = line.with_context(|| format!("Failed to read {}", path))?;
+                serde_json::from_str::<StreamMessage>(&line)
+                    .with_context(|| format!("Invalid message at {}:{}", path, number + 1))
+            })
+            .filter_map(move |message| future::ready(skip_handled(message)));
+
+        Ok(messages.boxed())
+    }
+}
+
+/// Writes everything `source` streams to `path`, one JSON message per line,
+/// for `ReplaySource` to read back. Reconnections append to the file.
+pub struct RecordingSource {
+    source: Arc<dyn DataSource>,
+    path: PathBuf,
+}
+
+impl RecordingSource {
+    pub fn new(source: Arc<dyn DataSource>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            source,
+            path: path.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl DataSource for RecordingSource {
+    async fn start(&self, resume: &ResumePoint) -> Result<BoxStream<'static, Result<StreamMessage>>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open recording {}", self.path.display()))?;
+        let file = Arc::new(Mutex::new(file));
+        let path = self.path.display().to_string();
+
+        let stream = self.source.start(resume).await?;
+        Ok(stream
+            .then(move |message| {
+                let file = file.clone();
+                let path = path.clone();
+                async move {
+                    let message = message?;
+                    let mut line = serde_json::to_string(&message)?;
+                    line.push('\n');
+                    file.lock()
+                        .await
+                        .write_all(line.as_bytes())
+                        .await
+                        .with_context(|| format!("Failed to record to {}", path))?;
+                    Ok::<_, anyhow::Error>(message)
+                }
+            })
+            .boxed())
+    }
+}
@@ -0,0 +1,843 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use apibara_core::node::v1alpha2::Cursor;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use starknet::core::types::{
+    BlockId, BlockStatus, BlockWithTxHashes, EmittedEvent, Event, EventFilter, Felt,
+    MaybePendingBlockWithReceipts, MaybePendingBlockWithTxHashes, MaybePendingStateUpdate,
+    TransactionReceipt,
+};
+use starknet::providers::jsonrpc::HttpTransport;
+use starknet::providers::{JsonRpcClient, Provider, Url};
+
+use crate::config::{Config, ContractConfig, Finality};
+use crate::dna::block::{BlockData, StreamMessage};
+use crate::dna::source::{DataSource, FatalSourceError};
+use crate::dna::state::ResumePoint;
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::transaction::{ReceiptData, TransactionData};
+use crate::dna::EventData;
+use crate::utils::conversions::felt_to_hex_string;
+
+/// Events per `starknet_getEvents` page.
+const EVENTS_CHUNK_SIZE: u64 = 1000;
+
+/// Blocks read per batch while catching up.
+const BLOCKS_PER_BATCH: u64 = 1000;
+
+/// Number of streamed block hashes kept to find where the chain forked.
+const REORG_WINDOW: usize = 64;
+
+/// How long the poller stays quiet at the head of the chain before it sends
+/// a heartbeat.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reads blocks from a Starknet node with `starknet_getEvents`, then
+/// `starknet_getBlockWithReceipts` for the blocks with matching events,
+/// producing the same messages as the Apibara stream. Events are sent in
+/// block order and, like with Apibara, `event_index` is the position of the
+/// event among all the events of its block.
+///
+/// Nodes don't serve pending data by number, blocks are streamed once
+/// accepted, or once accepted on L1 with the `finalized` finality.
+pub struct RpcPoller {
+    provider: JsonRpcClient<HttpTransport>,
+    contracts: Vec<ContractConfig>,
+    finality: Finality,
+    ending_block: Option<u64>,
+    include_transactions: bool,
+    include_receipts: bool,
+    include_state_updates: bool,
+    poll_interval: Duration,
+    next_block: u64,
+    /// Hashes of the last streamed blocks, checked against the node to
+    /// detect reorganizations.
+    recent: BTreeMap<u64, Felt>,
+    idle_since: Instant,
+}
+
+/// Polls the Starknet node at `config.source.rpc_url`, see `RpcPoller`.
+pub struct RpcSource {
+    config: Config,
+}
+
+impl RpcSource {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl DataSource for RpcSource {
+    async fn start(&self, resume: &ResumePoint) -> Result<BoxStream<'static, Result<StreamMessage>>> {
+        let poller = RpcPoller::new(&self.config, resume)?;
+        println!(
+            "✅ [Indexer] Polling Starknet RPC ({}) from block {}, listening ...",
+            self.config.network.name(),
+            poller.next_block
+        );
+
+        Ok(futures::stream::try_unfold(poller, |mut poller| async move {
+            let message = poller.next().await?;
+            Ok(Some((message, poller)))
+        })
+        .boxed())
+    }
+}
+
+impl RpcPoller {
+    pub fn new(config: &Config, resume: &ResumePoint) -> Result<Self> {
+        let url = config.source.rpc_url.as_deref().context("RPC_URL is not set")?;
+        let url = Url::parse(url).with_context(|| format!("Invalid RPC_URL {}", url))?;
+        if config.finality == Finality::Pending {
+            anyhow::bail!("The rpc source can't stream pending blocks, use the accepted or finalized finality");
+        }
+
+        let (next_block, recent) = match resume {
+            ResumePoint::Block(block_number) => (*block_number, BTreeMap::new()),
+            // The saved hash is checked on the first poll, like Apibara does
+            ResumePoint::Cursor(cursor) if !cursor.unique_key.is_empty() => (
+                cursor.order_key + 1,
+                BTreeMap::from([(cursor.order_key, Felt::from_bytes_be_slice(&cursor.unique_key))]),
+            ),
+            ResumePoint::Cursor(cursor) => (cursor.order_key + 1, BTreeMap::new()),
+        };
+
+        Ok(RpcPoller {
+            provider: JsonRpcClient::new(HttpTransport::new(url)),
+            contracts: config.contracts.clone(),
+            finality: config.finality,
+            ending_block: config.ending_block,
+            include_transactions: config.include_transactions,
+            include_receipts: config.include_receipts,
+            include_state_updates: config.include_state_updates,
+            poll_interval: Duration::from_millis(config.source.poll_interval_ms),
+            next_block,
+            recent,
+            idle_since: Instant::now(),
+        })
+    }
+
+    /// Waits for the next batch of blocks, a reorganization or a heartbeat.
+    pub async fn next(&mut self) -> Result<StreamMessage> {
+        loop {
+            if let Some(cursor) = self.find_reorg().await? {
+                return Ok(StreamMessage::Invalidate { cursor: Some(cursor) });
+            }
+
+            let head = self.head().await?;
+            let last = match (head, self.ending_block) {
+                (Some(head), Some(end)) => Some(head.min(end)),
+                (head, _) => head,
+            };
+            if let Some(last) = last.filter(|last| *last >= self.next_block) {
+                let to = last.min(self.next_block + BLOCKS_PER_BATCH - 1);
+                let blocks = self.fetch(self.next_block, to).await?;
+                self.next_block = to + 1;
+                self.idle_since = Instant::now();
+                return Ok(StreamMessage::Data {
+                    end_block: to,
+                    finality: self.finality,
+                    blocks,
+                });
+            }
+
+            if self.idle_since.elapsed() >= HEARTBEAT_INTERVAL {
+                self.idle_since = Instant::now();
+                return Ok(StreamMessage::Heartbeat);
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    async fn block(&self, block_number: u64) -> Result<BlockWithTxHashes> {
+        let block = self
+            .provider
+            .get_block_with_tx_hashes(BlockId::Number(block_number))
+            .await
+            .with_context(|| format!("Failed to get block {}", block_number))?;
+
+        match block {
+            MaybePendingBlockWithTxHashes::Block(block) => Ok(block),
+            MaybePendingBlockWithTxHashes::PendingBlock(_) => anyhow::bail!("Block {} is still pending", block_number),
+        }
+    }
+
+    /// Last block that can be streamed with the configured finality.
+    async fn head(&self) -> Result<Option<u64>> {
+        let latest = self
+            .provider
+            .block_number()
+            .await
+            .context("Failed to get the latest block number")?;
+        if self.finality < Finality::Finalized || latest < self.next_block {
+            return Ok(Some(latest));
+        }
+
+        // There is no block tag for L1 finality, look for the last block
+        // accepted on L1 between the next block and the latest one
+        let finalized = |block: BlockWithTxHashes| block.status == BlockStatus::AcceptedOnL1;
+        if !finalized(self.block(self.next_block).await?) {
+            return Ok(None);
+        }
+        let (mut low, mut high) = (self.next_block, latest);
+        while low < high {
+            let middle = low + (high - low + 1) / 2;
+            if finalized(self.block(middle).await?) {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    /// Compares the last streamed block with the node. When it changed,
+    /// returns the cursor of the most recent block still on the chain and
+    /// resumes after it.
+    async fn find_reorg(&mut self) -> Result<Option<Cursor>> {
+        let Some((&block_number, &hash)) = self.recent.last_key_value() else {
+            return Ok(None);
+        };
+        if self.block(block_number).await?.block_hash == hash {
+            return Ok(None);
+        }
+
+        let mut oldest = block_number;
+        self.recent.pop_last();
+        while let Some((block_number, hash)) = self.recent.pop_last() {
+            if self.block(block_number).await?.block_hash == hash {
+                self.recent.insert(block_number, hash);
+                self.next_block = block_number + 1;
+                return Ok(Some(Cursor {
+                    order_key: block_number,
+                    unique_key: hash.to_bytes_be().to_vec(),
+                }));
+            }
+            oldest = block_number;
+        }
+        // Nothing streamed before is known, rolling back is not possible
+        Err(FatalSourceError(format!(
+            "The chain was reorganized at or before block {}, deeper than the {} blocks the rpc source can roll \
+             back. Restore a checkpoint from before the reorganization or reindex from an earlier STARTING_BLOCK",
+            oldest, REORG_WINDOW
+        ))
+        .into())
+    }
+
+    fn remember(&mut self, block_number: u64, hash: Felt) {
+        // Blocks accepted on L1 can't be reorganized
+        if self.finality == Finality::Finalized {
+            return;
+        }
+        self.recent.insert(block_number, hash);
+        while self.recent.len() > REORG_WINDOW {
+            self.recent.pop_first();
+        }
+    }
+
+    /// `starknet_getEvents` filters matching the configured contracts and
+    /// events, keys being matched position by position.
+    fn filters(&self, from: u64, to: u64) -> Vec<EventFilter> {
+        let filter = |address: Felt, keys: Option<Vec<Vec<Felt>>>| EventFilter {
+            from_block: Some(BlockId::Number(from)),
+            to_block: Some(BlockId::Number(to)),
+            address: Some(address),
+            keys,
+        };
+
+        self.contracts
+            .iter()
+            .flat_map(|contract| {
+                if contract.events.is_empty() {
+                    return vec![filter(contract.address, None)];
+                }
+                contract
+                    .events
+                    .iter()
+                    .map(|event| {
+                        let keys = event.filter_keys().into_iter().map(|key| vec![key]).collect();
+                        filter(contract.address, Some(keys))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Reads the blocks from `from` to `to`, keeping the ones with matching
+    /// events or state changes.
+    async fn fetch(&mut self, from: u64, to: u64) -> Result<Vec<BlockData>> {
+        let mut events = BTreeMap::<u64, Vec<EmittedEvent>>::new();
+        for filter in self.filters(from, to) {
+            let mut matched = BTreeMap::<u64, Vec<EmittedEvent>>::new();
+            let mut continuation_token = None;
+            loop {
+                let page = self
+                    .provider
+                    .get_events(filter.clone(), continuation_token, EVENTS_CHUNK_SIZE)
+                    .await
+                    .with_context(|| format!("Failed to get events of blocks {}..={}", from, to))?;
+                for event in page.events {
+                    // Pending events have no block number
+                    if let Some(block_number) = event.block_number {
+                        matched.entry(block_number).or_default().push(event);
+                    }
+                }
+                continuation_token = page.continuation_token;
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+            for (block_number, matched) in matched {
+                merge_events(events.entry(block_number).or_default(), matched);
+            }
+        }
+
+        // State updates are only read for blocks with matching events, one
+        // request per block of the range would dwarf everything else
+        let mut state_diffs = BTreeMap::new();
+        if self.include_state_updates {
+            for &block_number in events.keys() {
+                let update = self
+                    .provider
+                    .get_state_update(BlockId::Number(block_number))
+                    .await
+                    .with_context(|| format!("Failed to get the state update of block {}", block_number))?;
+                if let MaybePendingStateUpdate::Update(update) = update {
+                    state_diffs.insert(block_number, update.state_diff);
+                }
+            }
+        }
+
+        let contracts = self.contracts.iter().map(|c| c.address).collect::<Vec<_>>();
+        let mut blocks = Vec::new();
+        for (block_number, matched) in events {
+            let (block_hash, block_events) = self.block_events(block_number, matched).await?;
+            let state_update = state_diffs.remove(&block_number).and_then(|diff| {
+                StateUpdateData::from_rpc(block_number, &block_hash, &diff, &contracts, self.finality)
+            });
+            self.remember(block_number, block_hash);
+            blocks.push(BlockData {
+                block_number,
+                block_hash: felt_to_hex_string(&block_hash),
+                events: block_events,
+                state_update,
+            });
+        }
+
+        // The last block of the range is checked for reorganizations on the next poll
+        if !self.recent.contains_key(&to) {
+            let header = self.block(to).await?;
+            self.remember(to, header.block_hash);
+        }
+        Ok(blocks)
+    }
+
+    /// Reads `block_number` with its receipts and returns its hash and the
+    /// `matched` events in block order, indexed among all of its events.
+    async fn block_events(&self, block_number: u64, mut matched: Vec<EmittedEvent>) -> Result<(Felt, Vec<EventData>)> {
+        let block = self
+            .provider
+            .get_block_with_receipts(BlockId::Number(block_number))
+            .await
+            .with_context(|| format!("Failed to get block {} with receipts", block_number))?;
+        let block = match block {
+            MaybePendingBlockWithReceipts::Block(block) => block,
+            MaybePendingBlockWithReceipts::PendingBlock(_) => anyhow::bail!("Block {} is still pending", block_number),
+        };
+        if matched.iter().any(|event| event.block_hash != Some(block.block_hash)) {
+            anyhow::bail!("Block {} changed while it was read", block_number);
+        }
+
+        let mut transactions = HashMap::new();
+        let mut events = Vec::with_capacity(matched.len());
+        let mut event_index = 0u64;
+        for (transaction_index, with_receipt) in block.transactions.iter().enumerate() {
+            let receipt = &with_receipt.receipt;
+            let hash = *receipt.transaction_hash();
+            for event in receipt_events(receipt) {
+                let position = matched.iter().position(|e| {
+                    e.transaction_hash == hash
+                        && e.from_address == event.from_address
+                        && e.keys == event.keys
+                        && e.data == event.data
+                });
+                if let Some(position) = position {
+                    matched.swap_remove(position);
+                    if self.include_transactions && !transactions.contains_key(&hash) {
+                        let transaction = self
+                            .provider
+                            .get_transaction_by_hash(hash)
+                            .await
+                            .with_context(|| format!("Failed to get transaction {}", hash.to_hex_string()))?;
+                        transactions.insert(hash, TransactionData::from(&transaction));
+                    }
+
+                    events.push(EventData {
+                        block_number,
+                        block_hash: felt_to_hex_string(&block.block_hash),
+                        timestamp: block.timestamp,
+                        transaction_hash: felt_to_hex_string(&hash),
+                        transaction_index: transaction_index as u64,
+                        event_index,
+                        from_address: felt_to_hex_string(&event.from_address),
+                        contract: String::new(),
+                        keys: event.keys.iter().map(felt_to_hex_string).collect(),
+                        data: event.data.iter().map(felt_to_hex_string).collect(),
+                        finality: self.finality,
+                        transaction: transactions.get(&hash).cloned(),
+                        receipt: self
+                            .include_receipts
+                            .then(|| ReceiptData::from_rpc(receipt, transaction_index as u64)),
+                        decoded: None,
+                    });
+                }
+                event_index += 1;
+            }
+        }
+        // Matched events missing from the receipts come from another version of the block
+        if !matched.is_empty() {
+            anyhow::bail!("Block {} changed while it was read", block_number);
+        }
+
+        Ok((block.block_hash, events))
+    }
+}
+
+/// Events emitted by a transaction, in order.
+fn receipt_events(receipt: &TransactionReceipt) -> &[Event] {
+    match receipt {
+        TransactionReceipt::Invoke(receipt) => &receipt.events,
+        TransactionReceipt::L1Handler(receipt) => &receipt.events,
+        TransactionReceipt::Declare(receipt) => &receipt.events,
+        TransactionReceipt::Deploy(receipt) => &receipt.events,
+        TransactionReceipt::DeployAccount(receipt) => &receipt.events,
+    }
+}
+
+/// Adds the events of a block matched by another filter. An event matched
+/// by several filters is kept once, identical events of a transaction are
+/// all kept.
+fn merge_events(merged: &mut Vec<EmittedEvent>, events: Vec<EmittedEvent>) {
+    let mut already_merged = merged.clone();
+    for event in events {
+        match already_merged.iter().position(|e| *e == event) {
+            Some(position) => {
+                already_merged.swap_remove(position);
+            }
+            None => merged.push(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{json, Value};
+    use starknet::core::utils::get_selector_from_name;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::{EventFilterConfig, SourceKind};
+
+    const VAULT: u64 = 0x0123;
+    const POOL: u64 = 0x0456;
+
+    /// An event emitted by a mock transaction: emitter, keys and data.
+    type MockEvent = (u64, Vec<Felt>, Vec<Felt>);
+
+    struct MockBlock {
+        hash: Felt,
+        /// Transaction hashes with their events.
+        transactions: Vec<(Felt, Vec<MockEvent>)>,
+    }
+
+    /// The chain served by the mock node, `starknet_getEvents` pages holding
+    /// at most `page_size` events.
+    struct MockChain {
+        blocks: BTreeMap<u64, MockBlock>,
+        page_size: usize,
+        /// Methods called, in order.
+        requests: Vec<String>,
+    }
+
+    impl MockChain {
+        fn new(page_size: usize) -> Self {
+            Self { blocks: BTreeMap::new(), page_size, requests: Vec::new() }
+        }
+
+        fn add_block(&mut self, block_number: u64, hash: u64, transactions: Vec<(u64, Vec<MockEvent>)>) {
+            let transactions = transactions
+                .into_iter()
+                .map(|(hash, events)| (Felt::from(hash), events))
+                .collect();
+            self.blocks.insert(block_number, MockBlock { hash: Felt::from(hash), transactions });
+        }
+
+        fn requests_of(&self, method: &str) -> usize {
+            self.requests.iter().filter(|m| *m == method).count()
+        }
+
+        fn handle(&mut self, method: &str, params: &Value) -> Value {
+            self.requests.push(method.to_string());
+            match method {
+                "starknet_blockNumber" => json!(self.blocks.keys().next_back().copied().unwrap_or_default()),
+                "starknet_getBlockWithTxHashes" => {
+                    let (block_number, block) = self.block(param(params, 0, "block_id"));
+                    let mut header = header(block_number, block);
+                    header["transactions"] = block.transactions.iter().map(|(hash, _)| json!(hash)).collect();
+                    header
+                }
+                "starknet_getBlockWithReceipts" => {
+                    let (block_number, block) = self.block(param(params, 0, "block_id"));
+                    let mut header = header(block_number, block);
+                    header["transactions"] = block
+                        .transactions
+                        .iter()
+                        .map(|(hash, events)| with_receipt(hash, events))
+                        .collect();
+                    header
+                }
+                "starknet_getEvents" => self.events(param(params, 0, "filter")),
+                _ => Value::Null,
+            }
+        }
+
+        fn block(&self, block_id: &Value) -> (u64, &MockBlock) {
+            let block_number = block_id["block_number"].as_u64().expect("blocks are read by number");
+            (block_number, &self.blocks[&block_number])
+        }
+
+        fn events(&self, filter: &Value) -> Value {
+            let from = filter["from_block"]["block_number"].as_u64().unwrap();
+            let to = filter["to_block"]["block_number"].as_u64().unwrap();
+            let address: Felt = serde_json::from_value(filter["address"].clone()).unwrap();
+            let keys: Vec<Vec<Felt>> = serde_json::from_value(filter["keys"].clone()).unwrap_or_default();
+            let matches = |emitter: u64, event_keys: &[Felt]| {
+                Felt::from(emitter) == address
+                    && keys.iter().enumerate().all(|(i, allowed)| {
+                        allowed.is_empty() || event_keys.get(i).is_some_and(|key| allowed.contains(key))
+                    })
+            };
+
+            let mut matched = Vec::new();
+            for (block_number, block) in self.blocks.range(from..=to) {
+                for (transaction_hash, events) in &block.transactions {
+                    for (emitter, event_keys, data) in events {
+                        if matches(*emitter, event_keys) {
+                            matched.push(json!({
+                                "from_address": Felt::from(*emitter),
+                                "keys": event_keys,
+                                "data": data,
+                                "block_hash": block.hash,
+                                "block_number": block_number,
+                                "transaction_hash": transaction_hash,
+                            }));
+                        }
+                    }
+                }
+            }
+
+            let start = filter["continuation_token"].as_str().map_or(0, |token| token.parse().unwrap());
+            let end = matched.len().min(start + self.page_size);
+            let mut page = json!({ "events": &matched[start..end] });
+            if end < matched.len() {
+                page["continuation_token"] = json!(end.to_string());
+            }
+            page
+        }
+    }
+
+    /// Positional or named JSON-RPC parameter.
+    fn param<'a>(params: &'a Value, index: usize, name: &str) -> &'a Value {
+        match params {
+            Value::Array(values) => &values[index],
+            _ => &params[name],
+        }
+    }
+
+    fn header(block_number: u64, block: &MockBlock) -> Value {
+        json!({
+            "status": "ACCEPTED_ON_L2",
+            "block_hash": block.hash,
+            "parent_hash": "0x0",
+            "block_number": block_number,
+            "new_root": "0x0",
+            "timestamp": 1_700_000_000 + block_number,
+            "sequencer_address": "0x0",
+            "l1_gas_price": { "price_in_fri": "0x0", "price_in_wei": "0x0" },
+            "l1_data_gas_price": { "price_in_fri": "0x0", "price_in_wei": "0x0" },
+            "l1_da_mode": "CALLDATA",
+            "starknet_version": "0.13.1",
+        })
+    }
+
+    fn with_receipt(hash: &Felt, events: &[MockEvent]) -> Value {
+        let events = events
+            .iter()
+            .map(|(emitter, keys, data)| json!({ "from_address": Felt::from(*emitter), "keys": keys, "data": data }))
+            .collect::<Vec<_>>();
+        json!({
+            "transaction": {
+                "type": "INVOKE",
+                "version": "0x1",
+                "transaction_hash": hash,
+                "sender_address": "0x1",
+                "calldata": [],
+                "max_fee": "0x0",
+                "signature": [],
+                "nonce": "0x0",
+            },
+            "receipt": {
+                "type": "INVOKE",
+                "transaction_hash": hash,
+                "actual_fee": { "amount": "0x0", "unit": "WEI" },
+                "finality_status": "ACCEPTED_ON_L2",
+                "execution_status": "SUCCEEDED",
+                "messages_sent": [],
+                "events": events,
+                "execution_resources": { "steps": 1, "data_availability": { "l1_gas": 0, "l1_data_gas": 0 } },
+            },
+        })
+    }
+
+    /// Serves `chain` over HTTP, returns the node URL.
+    async fn serve(chain: Arc<Mutex<MockChain>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let chain = chain.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    // Requests follow each other on a kept alive connection
+                    loop {
+                        let mut content_length = 0;
+                        loop {
+                            let mut line = String::new();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let line = line.trim_end();
+                            if line.is_empty() {
+                                break;
+                            }
+                            if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    content_length = value.trim().parse().unwrap();
+                                }
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        if stream.read_exact(&mut body).await.is_err() {
+                            return;
+                        }
+
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let result = chain
+                            .lock()
+                            .unwrap()
+                            .handle(request["method"].as_str().unwrap_or_default(), &request["params"]);
+                        let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string();
+                        let reply = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            response.len(),
+                            response
+                        );
+                        if stream.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    fn selector(name: &str) -> Felt {
+        get_selector_from_name(name).unwrap()
+    }
+
+    fn deposit(amount: u64) -> MockEvent {
+        (VAULT, vec![selector("Deposit")], vec![Felt::from(amount)])
+    }
+
+    async fn poller(chain: &Arc<Mutex<MockChain>>, events: Vec<EventFilterConfig>, resume: ResumePoint) -> RpcPoller {
+        let mut config = Config {
+            contracts: vec![ContractConfig {
+                name: "vault".to_string(),
+                address: Felt::from(VAULT),
+                events,
+                abi_path: None,
+                abi: None,
+            }],
+            finality: Finality::Accepted,
+            ..Config::default()
+        };
+        config.source.kind = SourceKind::Rpc;
+        config.source.rpc_url = Some(serve(chain.clone()).await);
+
+        RpcPoller::new(&config, &resume).unwrap()
+    }
+
+    /// Block number, position in the block, transaction index and first
+    /// data felt of the streamed events.
+    fn streamed(message: StreamMessage) -> Vec<(u64, u64, u64, Felt)> {
+        let blocks = match message {
+            StreamMessage::Data { blocks, .. } => blocks,
+            other => panic!("expected data, got {:?}", other),
+        };
+        blocks
+            .iter()
+            .flat_map(|block| &block.events)
+            .map(|event| {
+                let data = Felt::from_hex(&event.data[0]).unwrap();
+                (event.block_number, event.event_index, event.transaction_index, data)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn events_are_merged_in_block_order_across_pages() {
+        let chain = Arc::new(Mutex::new(MockChain::new(1)));
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.add_block(
+                10,
+                0x1010,
+                vec![
+                    (
+                        0xa,
+                        vec![
+                            (VAULT, vec![selector("Withdraw")], vec![Felt::from(1u8)]),
+                            (POOL, vec![selector("Deposit")], vec![Felt::from(2u8)]),
+                            (VAULT, vec![selector("Deposit"), Felt::from(5u8)], vec![Felt::from(3u8)]),
+                        ],
+                    ),
+                    // Identical events of a transaction are all streamed
+                    (0xb, vec![deposit(4), deposit(4)]),
+                ],
+            );
+            chain.add_block(
+                11,
+                0x1011,
+                vec![(0xc, vec![(VAULT, vec![selector("Transfer")], vec![Felt::from(5u8)]), deposit(6)])],
+            );
+        }
+        // The second filter matches an event of the first one again
+        let filters = vec![
+            EventFilterConfig::new("Deposit", Vec::new()).unwrap(),
+            EventFilterConfig::new("Deposit", vec![Felt::from(5u8)]).unwrap(),
+            EventFilterConfig::new("Withdraw", Vec::new()).unwrap(),
+        ];
+        let mut poller = poller(&chain, filters, ResumePoint::Block(10)).await;
+
+        let message = poller.next().await.unwrap();
+        assert_eq!(
+            streamed(message),
+            vec![
+                (10, 0, 0, Felt::from(1u8)),
+                (10, 2, 0, Felt::from(3u8)),
+                (10, 3, 1, Felt::from(4u8)),
+                (10, 4, 1, Felt::from(4u8)),
+                (11, 1, 0, Felt::from(6u8)),
+            ]
+        );
+        // One page per event: 4 deposits, 1 deposit with key 5 and 1 withdrawal
+        assert_eq!(chain.lock().unwrap().requests_of("starknet_getEvents"), 6);
+    }
+
+    #[tokio::test]
+    async fn a_reorganization_rolls_back_to_the_last_block_still_on_chain() {
+        let chain = Arc::new(Mutex::new(MockChain::new(100)));
+        for block_number in 10..=12 {
+            chain
+                .lock()
+                .unwrap()
+                .add_block(block_number, 0x1000 + block_number, vec![(block_number, vec![deposit(block_number)])]);
+        }
+        let mut poller = poller(&chain, Vec::new(), ResumePoint::Block(10)).await;
+        assert_eq!(streamed(poller.next().await.unwrap()).len(), 3);
+
+        // Blocks 11 and 12 are replaced, 12 without any event
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.add_block(11, 0x2011, vec![(0x21, vec![deposit(21)])]);
+            chain.add_block(12, 0x2012, Vec::new());
+        }
+        match poller.next().await.unwrap() {
+            StreamMessage::Invalidate { cursor: Some(cursor) } => {
+                assert_eq!(cursor.order_key, 10);
+                assert_eq!(Felt::from_bytes_be_slice(&cursor.unique_key), Felt::from(0x100au64));
+            }
+            message => panic!("expected an invalidation, got {:?}", message),
+        }
+
+        let message = poller.next().await.unwrap();
+        assert!(matches!(&message, StreamMessage::Data { end_block: 12, .. }), "{:?}", message);
+        assert_eq!(streamed(message), vec![(11, 0, 0, Felt::from(21u8))]);
+        assert!(poller.find_reorg().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_reorganization_deeper_than_the_window_is_fatal() {
+        let chain = Arc::new(Mutex::new(MockChain::new(100)));
+        for block_number in 0..70 {
+            chain
+                .lock()
+                .unwrap()
+                .add_block(block_number, 0x1000 + block_number, vec![(block_number, vec![deposit(block_number)])]);
+        }
+        let mut poller = poller(&chain, Vec::new(), ResumePoint::Block(0)).await;
+        assert_eq!(streamed(poller.next().await.unwrap()).len(), 70);
+        assert_eq!(poller.recent.len(), REORG_WINDOW);
+
+        for block in chain.lock().unwrap().blocks.values_mut() {
+            block.hash = block.hash + Felt::from(0x1000u64);
+        }
+        let error = poller.next().await.unwrap_err();
+        assert!(error.is::<FatalSourceError>(), "{:#}", error);
+        assert!(error.to_string().contains("at or before block 6"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn a_checkpoint_whose_block_changed_is_not_resumed_from() {
+        let chain = Arc::new(Mutex::new(MockChain::new(100)));
+        chain.lock().unwrap().add_block(9, 0x1009, Vec::new());
+        let cursor = Cursor {
+            order_key: 9,
+            unique_key: Felt::from(0x2009u64).to_bytes_be().to_vec(),
+        };
+        let mut poller = poller(&chain, Vec::new(), ResumePoint::Cursor(cursor)).await;
+
+        let error = poller.next().await.unwrap_err();
+        assert!(error.is::<FatalSourceError>(), "{:#}", error);
+    }
+
+    #[test]
+    fn events_matched_by_several_filters_are_merged_once() {
+        let event = |transaction_hash: u64, data: u64| EmittedEvent {
+            from_address: Felt::from(VAULT),
+            keys: vec![selector("Deposit")],
+            data: vec![Felt::from(data)],
+            block_hash: Some(Felt::from(0x1010u64)),
+            block_number: Some(10),
+            transaction_hash: Felt::from(transaction_hash),
+        };
+
+        let mut merged = vec![event(0xa, 1), event(0xa, 1), event(0xb, 2)];
+        merge_events(&mut merged, vec![event(0xa, 1), event(0xb, 2), event(0xb, 3)]);
+        assert_eq!(merged, vec![event(0xa, 1), event(0xa, 1), event(0xb, 2), event(0xb, 3)]);
+
+        // A second identical event only matched by the new filter is kept
+        merge_events(&mut merged, vec![event(0xb, 3), event(0xb, 3)]);
+        assert_eq!(merged.len(), 5);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::config::{Config, SourceKind};
+use crate::dna::apibara::ApibaraSource;
+use crate::dna::block::StreamMessage;
+use crate::dna::replay::{RecordingSource, ReplaySource};
+use crate::dna::rpc::RpcSource;
+use crate::dna::state::ResumePoint;
+
+/// Where the indexer reads blocks from.
+///
+/// A stream that ends means the source has nothing left to send and the
+/// indexer stops once consumers are done, sources that can go on should
+/// fail instead so the indexer reconnects. Errors that reconnecting can't
+/// fix are returned as a `FatalSourceError`.
+#[async_trait]
+pub trait DataSource: Send + Sync {
+    /// Streams the blocks after `resume`, called again with the point to
+    /// resume from whenever the previous stream failed.
+    async fn start(&self, resume: &ResumePoint) -> Result<BoxStream<'static, Result<StreamMessage>>>;
+}
+
+/// A source error the indexer stops with instead of reconnecting, e.g. a
+/// reorganization deeper than the source can roll back.
+#[derive(Debug)]
+pub struct FatalSourceError(pub String);
+
+impl std::fmt::Display for FatalSourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FatalSourceError {}
+
+/// The source picked by `config.source`, recording what it streams when
+/// `record_path` is set.
+pub fn from_config(config: &Config) -> Result<Arc<dyn DataSource>> {
+    let source: Arc<dyn DataSource> = match config.source.kind {
+        SourceKind::Apibara => Arc::new(ApibaraSource::new(config.clone())),
+        SourceKind::Rpc => Arc::new(RpcSource::new(config.clone())),
+        SourceKind::Replay => {
+            let path = config
+                .source
+                .replay_path
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("REPLAY_PATH is not set"))?;
+            Arc::new(ReplaySource::new(path))
+        }
+    };
+
+    Ok(match &config.source.record_path {
+        Some(path) => Arc::new(RecordingSource::new(source, path.clone())),
+        None => source,
+    })
+}
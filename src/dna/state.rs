@@ -0,0 +1,97 @@
+use anyhow::Result;
+use apibara_core::node::v1alpha2::Cursor;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::conversions::{bytes_to_hex_string, hex_string_to_bytes};
+
+/// Version of the `BlockState` layout written by this build.
+pub const STATE_VERSION: u32 = 1;
+
+/// Checkpoint persisted after each processed block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockState {
+    pub version: u32,
+    pub cursor: SavedCursor,
+    /// `Config::fingerprint` of the configuration that wrote the state.
+    pub config_fingerprint: String,
+}
+
+/// Serializable form of an Apibara `Cursor`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedCursor {
+    pub order_key: u64,
+    /// Block hash, hex encoded.
+    pub unique_key: String,
+}
+
+impl From<&Cursor> for SavedCursor {
+    fn from(cursor: &Cursor) -> Self {
+        SavedCursor {
+            order_key: cursor.order_key,
+            unique_key: bytes_to_hex_string(&cursor.unique_key),
+        }
+    }
+}
+
+impl SavedCursor {
+    pub fn to_cursor(&self) -> Result<Cursor> {
+        Ok(Cursor {
+            order_key: self.order_key,
+            unique_key: hex_string_to_bytes(&self.unique_key)?,
+        })
+    }
+}
+
+/// Any checkpoint layout this build can read.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StoredState {
+    Current(BlockState),
+    /// Written before cursors were persisted, only knows the block number.
+    Legacy { last_processed_block: u64 },
+}
+
+/// Where the stream resumes from.
+#[derive(Debug, Clone)]
+pub enum ResumePoint {
+    /// Start streaming at this block, inclusive.
+    Block(u64),
+    /// Start right after this cursor, Apibara invalidates it if the block
+    /// it points to was reorganized while the indexer was stopped.
+    Cursor(Cursor),
+}
+
+impl BlockState {
+    pub fn new(cursor: &Cursor, config_fingerprint: String) -> Self {
+        BlockState {
+            version: STATE_VERSION,
+            cursor: cursor.into(),
+            config_fingerprint,
+        }
+    }
+
+    /// Parses a stored checkpoint and tells where to resume from.
+    /// `fingerprint` is compared with the stored one to warn about
+    /// configuration changes.
+    pub fn parse(value: serde_json::Value, fingerprint: &str) -> Result<ResumePoint> {
+        match serde_json::from_value::<StoredState>(value)? {
+            StoredState::Current(state) => {
+                if state.version > STATE_VERSION {
+                    anyhow::bail!(
+                        "State file version {} is newer than the supported version {}",
+                        state.version,
+                        STATE_VERSION
+                    );
+                }
+                if state.config_fingerprint != fingerprint {
+                    println!(
+                        "⚠️ [Warning] The indexer configuration changed since block {} was checkpointed",
+                        state.cursor.order_key
+                    );
+                }
+                Ok(ResumePoint::Cursor(state.cursor.to_cursor()?))
+            }
+            StoredState::Legacy { last_processed_block } => Ok(ResumePoint::Block(last_processed_block)),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use apibara_core::starknet::v1alpha2::{BlockHeader, StateUpdate};
+use serde::{Deserialize, Serialize};
+use starknet::core::types::{Felt, StateDiff};
+
+use crate::config::Finality;
+use crate::utils::conversions::{felt_to_hex_string, optional_field_to_hex_string};
+
+/// State changes of a block that touch the configured contracts. Field
+/// elements are hex encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StateUpdateData {
+    pub block_number: u64,
+    pub block_hash: String,
+    pub finality: Finality,
+    pub storage_diffs: Vec<StorageDiffData>,
+    pub deployed_contracts: Vec<DeployedContractData>,
+    pub nonce_updates: Vec<NonceUpdateData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StorageDiffData {
+    pub contract_address: String,
+    pub entries: Vec<StorageEntryData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StorageEntryData {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeployedContractData {
+    pub contract_address: String,
+    pub class_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NonceUpdateData {
+    pub contract_address: String,
+    pub nonce: String,
+}
+
+impl StateUpdateData {
+    /// Flattens the state diff of a block, `None` when it is empty.
+    pub fn new(header: Option<&BlockHeader>, state_update: &StateUpdate, finality: Finality) -> Option<Self> {
+        let diff = state_update.state_diff.as_ref()?;
+
+        let update = StateUpdateData {
+            block_number: header.map(|hdr| hdr.block_number).unwrap_or(0),
+            block_hash: optional_field_to_hex_string(header.and_then(|hdr| hdr.block_hash.as_ref())),
+            finality,
+            storage_diffs: diff
+                .storage_diffs
+                .iter()
+                .map(|storage_diff| StorageDiffData {
+                    contract_address: optional_field_to_hex_string(storage_diff.contract_address.as_ref()),
+                    entries: storage_diff
+                        .storage_entries
+                        .iter()
+                        .map(|entry| StorageEntryData {
+                            key: optional_field_to_hex_string(entry.key.as_ref()),
+                            value: optional_field_to_hex_string(entry.value.as_ref()),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            deployed_contracts: diff
+                .deployed_contracts
+                .iter()
+                .map(|deployed| DeployedContractData {
+                    contract_address: optional_field_to_hex_string(deployed.contract_address.as_ref()),
+                    class_hash: optional_field_to_hex_string(deployed.class_hash.as_ref()),
+                })
+                .collect(),
+            nonce_updates: diff
+                .nonces
+                .iter()
+                .map(|nonce| NonceUpdateData {
+                    contract_address: optional_field_to_hex_string(nonce.contract_address.as_ref()),
+                    nonce: optional_field_to_hex_string(nonce.nonce.as_ref()),
+                })
+                .collect(),
+        };
+
+        Some(update).filter(|u| !u.is_empty())
+    }
+
+    /// Keeps the part of a `starknet_getStateUpdate` diff that touches
+    /// `contracts`, like the Apibara filter does. `None` when nothing does.
+    pub fn from_rpc(
+        block_number: u64,
+        block_hash: &Felt,
+        diff: &StateDiff,
+        contracts: &[Felt],
+        finality: Finality,
+    ) -> Option<Self> {
+        let update = StateUpdateData {
+            block_number,
+            block_hash: felt_to_hex_string(block_hash),
+            finality,
+            storage_diffs: diff
+                .storage_diffs
+                .iter()
+                .filter(|storage_diff| contracts.contains(&storage_diff.address))
+                .map(|storage_diff| StorageDiffData {
+                    contract_address: felt_to_hex_string(&storage_diff.address),
+                    entries: storage_diff
+                        .storage_entries
+                        .iter()
+                        .map(|entry| StorageEntryData {
+                            key: felt_to_hex_string(&entry.key),
+                            value: felt_to_hex_string(&entry.value),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            deployed_contracts: diff
+                .deployed_contracts
+                .iter()
+                .filter(|deployed| contracts.contains(&deployed.address))
+                .map(|deployed| DeployedContractData {
+                    contract_address: felt_to_hex_string(&deployed.address),
+                    class_hash: felt_to_hex_string(&deployed.class_hash),
+                })
+                .collect(),
+            nonce_updates: diff
+                .nonces
+                .iter()
+                .filter(|nonce| contracts.contains(&nonce.contract_address))
+                .map(|nonce| NonceUpdateData {
+                    contract_address: felt_to_hex_string(&nonce.contract_address),
+                    nonce: felt_to_hex_string(&nonce.nonce),
+                })
+                .collect(),
+        };
+
+        Some(update).filter(|u| !u.is_empty())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage_diffs.is_empty()
+            && self.deployed_contracts.is_empty()
+            && self.nonce_updates.is_empty()
+    }
+}
@@ -0,0 +1,181 @@
+use apibara_core::starknet::v1alpha2::{
+    transaction, ExecutionStatus, FieldElement, Transaction, TransactionReceipt,
+};
+use serde::{Deserialize, Serialize};
+use starknet::core::types::{
+    DeclareTransaction, DeployAccountTransaction, ExecutionResult, Felt, InvokeTransaction,
+    Transaction as RpcTransaction, TransactionReceipt as RpcReceipt,
+};
+
+use crate::utils::conversions::{felt_to_hex_string, field_to_hex_string, optional_field_to_hex_string};
+
+/// The transaction that emitted an event. Field elements are hex encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionData {
+    pub hash: String,
+    /// Transaction type, e.g. `invoke_v1` or `l1_handler`.
+    pub kind: String,
+    /// Account that sent the transaction, or the called contract for
+    /// `invoke_v0` and `l1_handler` transactions.
+    pub sender_address: Option<String>,
+    pub calldata: Vec<String>,
+    pub max_fee: String,
+    pub nonce: String,
+    pub version: u64,
+    pub signature: Vec<String>,
+}
+
+/// Receipt of the transaction that emitted an event.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceiptData {
+    pub transaction_hash: String,
+    pub transaction_index: u64,
+    pub actual_fee: String,
+    /// `succeeded` or `reverted`.
+    pub execution_status: String,
+    pub revert_reason: Option<String>,
+    /// Contract deployed by the transaction, if any.
+    pub contract_address: Option<String>,
+}
+
+fn hex_all(fields: &[FieldElement]) -> Vec<String> {
+    fields.iter().map(field_to_hex_string).collect()
+}
+
+impl From<&Transaction> for TransactionData {
+    fn from(tx: &Transaction) -> Self {
+        use transaction::Transaction as Kind;
+
+        let (kind, sender_address, calldata) = match &tx.transaction {
+            Some(Kind::InvokeV0(invoke)) => ("invoke_v0", invoke.contract_address.as_ref(), hex_all(&invoke.calldata)),
+            Some(Kind::InvokeV1(invoke)) => ("invoke_v1", invoke.sender_address.as_ref(), hex_all(&invoke.calldata)),
+            Some(Kind::InvokeV3(invoke)) => ("invoke_v3", invoke.sender_address.as_ref(), hex_all(&invoke.calldata)),
+            Some(Kind::L1Handler(handler)) => ("l1_handler", handler.contract_address.as_ref(), hex_all(&handler.calldata)),
+            Some(Kind::Declare(declare)) => ("declare", declare.sender_address.as_ref(), Vec::new()),
+            Some(Kind::Deploy(_)) => ("deploy", None, Vec::new()),
+            Some(Kind::DeployAccount(_)) => ("deploy_account", None, Vec::new()),
+            Some(_) => ("other", None, Vec::new()),
+            None => ("unknown", None, Vec::new()),
+        };
+        let meta = tx.meta.as_ref();
+
+        TransactionData {
+            hash: optional_field_to_hex_string(meta.and_then(|m| m.hash.as_ref())),
+            kind: kind.to_string(),
+            sender_address: sender_address.map(field_to_hex_string),
+            calldata,
+            max_fee: optional_field_to_hex_string(meta.and_then(|m| m.max_fee.as_ref())),
+            nonce: optional_field_to_hex_string(meta.and_then(|m| m.nonce.as_ref())),
+            version: meta.map(|m| m.version).unwrap_or_default(),
+            signature: meta.map(|m| hex_all(&m.signature)).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&TransactionReceipt> for ReceiptData {
+    fn from(receipt: &TransactionReceipt) -> Self {
+        let execution_status = match receipt.execution_status() {
+            ExecutionStatus::Reverted => "reverted",
+            _ => "succeeded",
+        };
+
+        ReceiptData {
+            transaction_hash: optional_field_to_hex_string(receipt.transaction_hash.as_ref()),
+            transaction_index: receipt.transaction_index,
+            actual_fee: optional_field_to_hex_string(receipt.actual_fee.as_ref()),
+            execution_status: execution_status.to_string(),
+            revert_reason: Some(receipt.revert_reason.clone()).filter(|r| !r.is_empty()),
+            contract_address: receipt.contract_address.as_ref().map(field_to_hex_string),
+        }
+    }
+}
+
+fn felts(fields: &[Felt]) -> Vec<String> {
+    fields.iter().map(felt_to_hex_string).collect()
+}
+
+/// Transactions as returned by `starknet_getTransactionByHash`.
+impl From<&RpcTransaction> for TransactionData {
+    fn from(tx: &RpcTransaction) -> Self {
+        let zero = Felt::ZERO;
+        let (kind, sender_address, calldata, max_fee, nonce, version, signature) = match tx {
+            RpcTransaction::Invoke(InvokeTransaction::V0(invoke)) => (
+                "invoke_v0", Some(&invoke.contract_address), felts(&invoke.calldata),
+                invoke.max_fee, zero, 0, felts(&invoke.signature),
+            ),
+            RpcTransaction::Invoke(InvokeTransaction::V1(invoke)) => (
+                "invoke_v1", Some(&invoke.sender_address), felts(&invoke.calldata),
+                invoke.max_fee, invoke.nonce, 1, felts(&invoke.signature),
+            ),
+            RpcTransaction::Invoke(InvokeTransaction::V3(invoke)) => (
+                "invoke_v3", Some(&invoke.sender_address), felts(&invoke.calldata),
+                zero, invoke.nonce, 3, felts(&invoke.signature),
+            ),
+            RpcTransaction::L1Handler(handler) => (
+                "l1_handler", Some(&handler.contract_address), felts(&handler.calldata),
+                zero, Felt::from(handler.nonce), 0, Vec::new(),
+            ),
+            RpcTransaction::Declare(DeclareTransaction::V0(declare)) => (
+                "declare", Some(&declare.sender_address), Vec::new(),
+                declare.max_fee, zero, 0, felts(&declare.signature),
+            ),
+            RpcTransaction::Declare(DeclareTransaction::V1(declare)) => (
+                "declare", Some(&declare.sender_address), Vec::new(),
+                declare.max_fee, declare.nonce, 1, felts(&declare.signature),
+            ),
+            RpcTransaction::Declare(DeclareTransaction::V2(declare)) => (
+                "declare", Some(&declare.sender_address), Vec::new(),
+                declare.max_fee, declare.nonce, 2, felts(&declare.signature),
+            ),
+            RpcTransaction::Declare(DeclareTransaction::V3(declare)) => (
+                "declare", Some(&declare.sender_address), Vec::new(),
+                zero, declare.nonce, 3, felts(&declare.signature),
+            ),
+            RpcTransaction::Deploy(_) => ("deploy", None, Vec::new(), zero, zero, 0, Vec::new()),
+            RpcTransaction::DeployAccount(DeployAccountTransaction::V1(deploy)) => (
+                "deploy_account", None, Vec::new(),
+                deploy.max_fee, deploy.nonce, 1, felts(&deploy.signature),
+            ),
+            RpcTransaction::DeployAccount(DeployAccountTransaction::V3(deploy)) => (
+                "deploy_account", None, Vec::new(),
+                zero, deploy.nonce, 3, felts(&deploy.signature),
+            ),
+        };
+
+        TransactionData {
+            hash: felt_to_hex_string(tx.transaction_hash()),
+            kind: kind.to_string(),
+            sender_address: sender_address.map(felt_to_hex_string),
+            calldata,
+            max_fee: felt_to_hex_string(&max_fee),
+            nonce: felt_to_hex_string(&nonce),
+            version,
+            signature,
+        }
+    }
+}
+
+impl ReceiptData {
+    /// Reads a `starknet_getTransactionReceipt` receipt, which doesn't say
+    /// where the transaction is in its block.
+    pub fn from_rpc(receipt: &RpcReceipt, transaction_index: u64) -> Self {
+        let revert_reason = match receipt.execution_result() {
+            ExecutionResult::Reverted { reason } => Some(reason.clone()),
+            ExecutionResult::Succeeded => None,
+        };
+        let contract_address = match receipt {
+            RpcReceipt::Deploy(deploy) => Some(&deploy.contract_address),
+            RpcReceipt::DeployAccount(deploy) => Some(&deploy.contract_address),
+            _ => None,
+        };
+
+        ReceiptData {
+            transaction_hash: felt_to_hex_string(receipt.transaction_hash()),
+            transaction_index,
+            actual_fee: felt_to_hex_string(&receipt.actual_fee().amount),
+            execution_status: if revert_reason.is_some() { "reverted" } else { "succeeded" }.to_string(),
+            revert_reason,
+            contract_address: contract_address.map(felt_to_hex_string),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use starknet::core::types::Felt;
+
+use crate::dna::ack::BlockEnd;
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::{EventData, IndexerMessage};
+
+/// User code run for the messages of the indexer. Only `on_event` is
+/// required, the other hooks do nothing by default.
+///
+/// A handler error stops the consumer before the block is acknowledged, so
+/// the block is streamed again on the next run.
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    /// Called for every event routed to this handler.
+    async fn on_event(&self, event: &EventData) -> Result<()>;
+
+    /// Called once every message of a block was handled.
+    async fn on_block_end(&self, _block: &BlockEnd) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the chain is reorganized: everything after
+    /// `block_number`, or everything when `None`, must be undone,
+    /// `invalidated` lists the events that were emitted for those blocks.
+    async fn on_rollback(&self, _block_number: Option<u64>, _invalidated: &[EventData]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the state changes of a block when
+    /// `include_state_updates` is enabled.
+    async fn on_state_update(&self, _update: &StateUpdateData) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Which events a handler receives, `None` matches anything.
+struct Route {
+    contract: Option<Felt>,
+    selector: Option<Felt>,
+    handler: usize,
+}
+
+impl Route {
+    fn matches(&self, contract: Option<Felt>, selector: Option<Felt>) -> bool {
+        self.contract.map_or(true, |c| Some(c) == contract)
+            && self.selector.map_or(true, |s| Some(s) == selector)
+    }
+}
+
+/// Routes indexer messages to the registered handlers. Events go to the
+/// handlers registered for their contract address and selector, block,
+/// rollback and state update notifications go to every handler.
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: Vec<Arc<dyn EventHandler>>,
+    routes: Vec<Route>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends every event to `handler`.
+    pub fn add(&mut self, handler: Arc<dyn EventHandler>) -> &mut Self {
+        self.route(None, None, handler)
+    }
+
+    /// Sends the events emitted by `contract` to `handler`.
+    pub fn add_for_contract(&mut self, contract: Felt, handler: Arc<dyn EventHandler>) -> &mut Self {
+        self.route(Some(contract), None, handler)
+    }
+
+    /// Sends the events of `contract` whose first key is `selector` to
+    /// `handler`, see `starknet::core::utils::get_selector_from_name`.
+    pub fn add_for_event(&mut self, contract: Felt, selector: Felt, handler: Arc<dyn EventHandler>) -> &mut Self {
+        self.route(Some(contract), Some(selector), handler)
+    }
+
+    fn route(&mut self, contract: Option<Felt>, selector: Option<Felt>, handler: Arc<dyn EventHandler>) -> &mut Self {
+        // A handler added several times is still called once per message
+        let index = match self.handlers.iter().position(|h| Arc::ptr_eq(h, &handler)) {
+            Some(index) => index,
+            None => {
+                self.handlers.push(handler);
+                self.handlers.len() - 1
+            }
+        };
+        self.routes.push(Route { contract, selector, handler: index });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Handlers `event` is routed to, each one at most once.
+    fn handlers_for(&self, event: &EventData) -> Vec<&Arc<dyn EventHandler>> {
+        let contract = Felt::from_hex(&event.from_address).ok();
+        let selector = event.keys.first().and_then(|key| Felt::from_hex(key).ok());
+
+        let mut matched = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(contract, selector))
+            .map(|route| route.handler)
+            .collect::<Vec<_>>();
+        matched.sort_unstable();
+        matched.dedup();
+
+        matched.into_iter().map(|index| &self.handlers[index]).collect()
+    }
+
+    /// Runs the handlers concerned by `message`, stopping at the first error.
+    pub async fn dispatch(&self, message: &IndexerMessage) -> Result<()> {
+        match message {
+            IndexerMessage::Event(event) => {
+                for handler in self.handlers_for(event) {
+                    handler.on_event(event).await?;
+                }
+            }
+            IndexerMessage::StateUpdate(update) => {
+                for handler in &self.handlers {
+                    handler.on_state_update(update).await?;
+                }
+            }
+            IndexerMessage::BlockEnd(block) => {
+                for handler in &self.handlers {
+                    handler.on_block_end(block).await?;
+                }
+            }
+            IndexerMessage::Rollback { block_number, invalidated } => {
+                for handler in &self.handlers {
+                    handler.on_rollback(*block_number, invalidated).await?;
+                }
+            }
+            IndexerMessage::Promoted { .. } | IndexerMessage::BackfillComplete(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::config::Finality;
+    use crate::utils::conversions::felt_to_hex_string;
+
+    const VAULT: u64 = 0x0123;
+    const POOL: u64 = 0x0456;
+    const DEPOSIT: u64 = 0x99;
+    const WITHDRAW: u64 = 0x98;
+
+    /// Records the calls it receives.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn calls(&self) -> Vec<String> {
+            std::mem::take(&mut *self.calls.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn on_event(&self, event: &EventData) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("event {}", event.event_index));
+            Ok(())
+        }
+
+        async fn on_block_end(&self, block: &BlockEnd) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("end {}", block.block_number));
+            Ok(())
+        }
+
+        async fn on_rollback(&self, block_number: Option<u64>, invalidated: &[EventData]) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("rollback {:?} {}", block_number, invalidated.len()));
+            Ok(())
+        }
+    }
+
+    fn event(event_index: u64, contract: u64, selector: u64) -> IndexerMessage {
+        IndexerMessage::Event(EventData {
+            block_number: 10,
+            block_hash: "0x0a".to_string(),
+            timestamp: 0,
+            transaction_hash: "0x01".to_string(),
+            transaction_index: 0,
+            event_index,
+            from_address: felt_to_hex_string(&Felt::from(contract)),
+            contract: "test".to_string(),
+            keys: vec![felt_to_hex_string(&Felt::from(selector))],
+            data: Vec::new(),
+            finality: Finality::Accepted,
+            transaction: None,
+            receipt: None,
+            decoded: None,
+        })
+    }
+
+    async fn dispatch_all(registry: &HandlerRegistry) {
+        let messages = [
+            event(0, VAULT, DEPOSIT),
+            event(1, VAULT, WITHDRAW),
+            event(2, POOL, DEPOSIT),
+            IndexerMessage::BlockEnd(BlockEnd { block_number: 10, finality: Finality::Accepted, sequence: 0 }),
+            IndexerMessage::Rollback { block_number: Some(9), invalidated: Vec::new() },
+        ];
+        for message in &messages {
+            registry.dispatch(message).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_routed_by_contract_and_selector() {
+        let (everything, vault, deposits) = (
+            Arc::new(Recorder::default()),
+            Arc::new(Recorder::default()),
+            Arc::new(Recorder::default()),
+        );
+        let mut registry = HandlerRegistry::new();
+        registry
+            .add(everything.clone())
+            .add_for_contract(Felt::from(VAULT), vault.clone())
+            .add_for_event(Felt::from(VAULT), Felt::from(DEPOSIT), deposits.clone());
+
+        dispatch_all(&registry).await;
+
+        assert_eq!(
+            everything.calls(),
+            vec!["event 0", "event 1", "event 2", "end 10", "rollback Some(9) 0"]
+        );
+        assert_eq!(vault.calls(), vec!["event 0", "event 1", "end 10", "rollback Some(9) 0"]);
+        assert_eq!(deposits.calls(), vec!["event 0", "end 10", "rollback Some(9) 0"]);
+    }
+
+    #[tokio::test]
+    async fn a_handler_added_several_times_is_called_once() {
+        let handler = Arc::new(Recorder::default());
+        let mut registry = HandlerRegistry::new();
+        registry
+            .add_for_contract(Felt::from(VAULT), handler.clone())
+            .add_for_event(Felt::from(VAULT), Felt::from(DEPOSIT), handler.clone())
+            .add_for_event(Felt::from(POOL), Felt::from(DEPOSIT), handler.clone());
+
+        dispatch_all(&registry).await;
+
+        assert_eq!(
+            handler.calls(),
+            vec!["event 0", "event 1", "event 2", "end 10", "rollback Some(9) 0"]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_registry_ignores_messages() {
+        let registry = HandlerRegistry::new();
+        assert!(registry.is_empty());
+
+        dispatch_all(&registry).await;
+    }
+}
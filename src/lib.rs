@@ -1,4 +1,10 @@
+pub mod abi;
 pub mod dna;
 pub mod config;
+pub mod handlers;
+pub mod runtime;
 pub mod services;
-pub mod utils;
\ No newline at end of file
+pub mod sinks;
+pub mod utils;
+
+pub use runtime::{Kanshi, KanshiBuilder, ShutdownHandle};
@@ -1,69 +1,92 @@
-use apibara_core::starknet::v1alpha2::Event;
-use config::Config;
-use dna::IndexerService;
-use tokio::{sync::mpsc, task};
+use std::process::ExitCode;
 
-mod dna;
-mod config;
-mod utils;
-mod services;
+use kanshi::config::Config;
+use kanshi::handlers::HandlerRegistry;
+use kanshi::KanshiBuilder;
+
+/// Exit code of a run stopped by a second signal, following the shell
+/// convention for SIGINT.
+const FORCED_EXIT_CODE: i32 = 130;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     print_banner();
     
-    // Create a channel for event communication
-    let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
-    
     // Load configurations
-    let config = match Config::new() {
+    let config = match Config::new().await {
         Ok(config) => {
             println!("Configurations loaded ✓");
             config
         }
         Err(e) => {
             eprintln!("Failed to load configuration ❗️ {}", e);
-            return;
+            return ExitCode::from(2);
         }
     };
 
-    // Create the IndexerService instance
-    let mut service = IndexerService::new(config);
-    
-    // Spawn the indexer service in a separate task
-    let indexer_handle = task::spawn(async move {
-        if let Err(e) = service.await.run_forever_simplified(&tx).await {
-            eprintln!("Error running Indexer ❗️ {:#}", e);
-        }
-    });
+    if config.validate_only {
+        println!("Configuration is valid ✓");
+        return ExitCode::SUCCESS;
+    }
 
-    // Spawn the event consumer in a separate task
-    let consumer_handle = task::spawn(async move {
-        while let Some(event) = rx.recv().await {
-            println!("🔥 Received Event: {:?}\n\n", event);
-            // Add your event processing logic here
-            // For example:
-            process_event(event).await;
+    // Register event handlers here, e.g. `handlers.add_for_contract(address, Arc::new(MyHandler))`
+    let handlers = HandlerRegistry::new();
+
+    // Create the indexer, connecting the sink and loading the checkpoint
+    let kanshi = match KanshiBuilder::new(config).with_handlers(handlers).build().await {
+        Ok(kanshi) => kanshi,
+        Err(e) => {
+            eprintln!("Failed to create Indexer ❗️ {:#}", e);
+            return ExitCode::FAILURE;
         }
+    };
+
+    // Stop gracefully on the first SIGINT/SIGTERM, immediately on the second
+    let shutdown = kanshi.shutdown_handle();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        println!("🛑 Shutting down, finishing in-flight events (signal again to force)...");
+        shutdown.shutdown();
+        wait_for_signal().await;
+        eprintln!("Forced shutdown ❗️ the last blocks may be processed again on restart");
+        std::process::exit(FORCED_EXIT_CODE);
     });
 
-    // Wait for both tasks to complete
-    tokio::select! {
-        _ = indexer_handle => println!("Indexer task completed"),
-        _ = consumer_handle => println!("Consumer task completed"),
+    // Returns once the indexer is done and every event is handled
+    match kanshi.run().await {
+        Ok(()) => {
+            println!("Indexer completed");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error running Indexer ❗️ {:#}", e);
+            ExitCode::FAILURE
+        }
     }
 }
 
-async fn process_event(event: Event) {
-    // Add your event processing logic here
-    // For example:
-    match event {
-        // Add pattern matching for different event types
-        _ => {
-            // Default processing
-            println!("Processing event: {:?}", event);
+/// Resolves on Ctrl+C, or SIGTERM on unix.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
         }
     }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
 fn print_banner() {
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::sync::{mpsc, watch};
+
+use crate::config::{Config, SinkKind};
+use crate::dna::ack::AckHandle;
+use crate::dna::source::DataSource;
+use crate::dna::{IndexerMessage, IndexerService};
+use crate::handlers::{EventHandler, HandlerRegistry};
+use crate::services::dataStore::StorageManager;
+use crate::sinks::{Sink, StdoutSink, StorageSink};
+
+/// Assembles an indexer from a `Config`, event handlers and a sink.
+///
+/// ```ignore
+/// let kanshi = KanshiBuilder::new(config)
+///     .with_handler(Arc::new(MyHandler))
+///     .build()
+///     .await?;
+/// let shutdown = kanshi.shutdown_handle();
+/// kanshi.run().await?;
+/// ```
+pub struct KanshiBuilder {
+    config: Config,
+    handlers: HandlerRegistry,
+    sink: Option<Arc<dyn Sink>>,
+    source: Option<Arc<dyn DataSource>>,
+}
+
+impl KanshiBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            handlers: HandlerRegistry::new(),
+            sink: None,
+            source: None,
+        }
+    }
+
+    /// Sends every event to `handler`.
+    pub fn with_handler(mut self, handler: Arc<dyn EventHandler>) -> Self {
+        self.handlers.add(handler);
+        self
+    }
+
+    /// Replaces the handlers with `handlers`, to route events by contract
+    /// and selector.
+    pub fn with_handlers(mut self, handlers: HandlerRegistry) -> Self {
+        self.handlers = handlers;
+        self
+    }
+
+    /// Writes records to `sink` instead of the one picked by `config.sink`.
+    pub fn with_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Reads blocks from `source` instead of the one picked by
+    /// `config.source`, e.g. a `ReplaySource` to run offline.
+    pub fn with_source(mut self, source: Arc<dyn DataSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Checks the configuration, connects the sink and loads the checkpoint.
+    pub async fn build(mut self) -> Result<Kanshi> {
+        self.config.load_abis()?;
+        // A source given in code replaces the configured one and its settings
+        match &self.source {
+            Some(_) => self.config.validate_without_source()?,
+            None => self.config.validate()?,
+        }
+
+        let sink = match self.sink {
+            Some(sink) => sink,
+            None => match self.config.sink.kind {
+                SinkKind::Stdout => Arc::new(StdoutSink),
+                SinkKind::Storage => {
+                    let storage = StorageManager::new(&self.config)
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Failed to connect to storage: {}", e))?;
+                    Arc::new(StorageSink::new(Arc::new(storage), self.config.sink.key_prefix.clone()))
+                }
+            },
+        };
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let mut service = IndexerService::new(self.config.clone())
+            .await?
+            .with_shutdown(shutdown_rx);
+        if let Some(source) = self.source {
+            service = service.with_source(source);
+        }
+
+        Ok(Kanshi {
+            channel_capacity: self.config.channel_capacity,
+            service,
+            handlers: self.handlers,
+            sink,
+            shutdown: ShutdownHandle { shutdown: Arc::new(shutdown) },
+        })
+    }
+}
+
+/// Stops a running indexer, it can be cloned and used from any task.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// Asks the indexer to stop streaming. `Kanshi::run` returns once the
+    /// messages already received are handled, the sink is flushed and the
+    /// last acknowledged block is checkpointed.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+/// An indexer ready to run, see `KanshiBuilder`.
+pub struct Kanshi {
+    channel_capacity: usize,
+    service: IndexerService,
+    handlers: HandlerRegistry,
+    sink: Arc<dyn Sink>,
+    shutdown: ShutdownHandle,
+}
+
+impl Kanshi {
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Streams until the `ending_block` is reached, the source ends, a
+    /// shutdown is requested or an error occurs in the stream, a handler or
+    /// the sink.
+    pub async fn run(self) -> Result<()> {
+        let Kanshi { channel_capacity, mut service, handlers, sink, .. } = self;
+
+        // Bounded so a slow consumer throttles the stream instead of buffering
+        let (tx, rx) = mpsc::channel::<IndexerMessage>(channel_capacity);
+        let ack = service.ack_handle();
+
+        let indexer = tokio::spawn(async move { service.run_forever_simplified(&tx).await });
+        // A consumer error drops the receiver, which stops the indexer
+        let consumed = consume(rx, &handlers, sink.as_ref(), &ack).await;
+        let indexed = indexer.await.context("Indexer task panicked")?;
+
+        consumed.and(indexed)
+    }
+}
+
+/// Hands every message to the handlers and the sink, then acknowledges the
+/// blocks they are done with.
+async fn consume(
+    mut rx: mpsc::Receiver<IndexerMessage>,
+    handlers: &HandlerRegistry,
+    sink: &dyn Sink,
+    ack: &AckHandle,
+) -> Result<()> {
+    while let Some(message) = rx.recv().await {
+        // Stop before acknowledging the block, it is streamed again on restart
+        handlers.dispatch(&message).await.context("Event handler failed")?;
+
+        match &message {
+            IndexerMessage::Event(event) => sink.write_event(event).await?,
+            IndexerMessage::StateUpdate(update) => sink.write_state_update(update).await?,
+            IndexerMessage::Rollback { block_number, invalidated } => {
+                match block_number {
+                    Some(block_number) => println!("⏪ Rollback to block {} ({} events invalidated)\n\n", block_number, invalidated.len()),
+                    None => println!("⏪ Rollback of everything streamed ({} events invalidated)\n\n", invalidated.len()),
+                }
+                sink.rollback(*block_number, invalidated).await?;
+            }
+            IndexerMessage::Promoted { block_number, finality } => {
+                println!("⬆️ Block {} is now {:?}\n\n", block_number, finality);
+            }
+            IndexerMessage::BlockEnd(block_end) => {
+                // The checkpoint must not move past records the sink still buffers
+                sink.flush().await?;
+                ack.ack(block_end);
+            }
+            IndexerMessage::BackfillComplete(summary) => {
+                println!("🏁 Backfill complete: {}", summary);
+            }
+        }
+    }
+
+    sink.flush().await
+}
@@ -0,0 +1,236 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::dataStore::{StorageManager, TypedStorage};
+
+/// Where the indexer persists its checkpoint.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    async fn load(&self) -> Result<Option<Value>>;
+    async fn save(&self, state: &Value) -> Result<()>;
+    async fn clear(&self) -> Result<()>;
+}
+
+/// Keeps the checkpoint in a JSON file on the local disk. Writes go to a
+/// temporary file that is synced then renamed over the checkpoint, and the
+/// previous checkpoint is kept as a `.bak` file next to it. File I/O runs on
+/// the blocking thread pool so it never stalls the stream.
+#[derive(Clone)]
+pub struct FileCheckpointStore {
+    path: PathBuf,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Path next to the checkpoint with `suffix` appended to its file name.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self
+            .path
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_default();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    pub fn backup_path(&self) -> PathBuf {
+        self.sibling(".bak")
+    }
+
+    /// Makes the rename durable by syncing the directory that holds the file.
+    fn sync_parent_dir(&self) -> Result<()> {
+        #[cfg(unix)]
+        {
+            let parent = match self.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Runs `io` on the blocking thread pool.
+    async fn blocking<T, F>(&self, io: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || io(&store))
+            .await
+            .context("Checkpoint I/O task panicked")?
+    }
+
+    fn read(&self) -> Result<Option<Value>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let state_json = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read checkpoint {}", self.path.display()))?;
+        let state = serde_json::from_str(&state_json).with_context(|| {
+            format!(
+                "Checkpoint {} is corrupt, restore it from {} or delete it to reindex from the starting block",
+                self.path.display(),
+                self.backup_path().display()
+            )
+        })?;
+
+        Ok(Some(state))
+    }
+
+    fn write(&self, state_json: &str) -> Result<()> {
+        let tmp_path = self.sibling(".tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(state_json.as_bytes())?;
+            file.sync_all()?;
+        }
+
+        if self.path.exists() {
+            let backup_path = self.backup_path();
+            fs::copy(&self.path, &backup_path)?;
+            OpenOptions::new().write(true).open(&backup_path)?.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.sync_parent_dir()
+    }
+
+    fn remove(&self) -> Result<()> {
+        if self.path.exists() {
+            fs::rename(&self.path, self.backup_path())?;
+            self.sync_parent_dir()?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn load(&self) -> Result<Option<Value>> {
+        self.blocking(Self::read).await
+    }
+
+    async fn save(&self, state: &Value) -> Result<()> {
+        let state_json = state.to_string();
+        self.blocking(move |store| store.write(&state_json)).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.blocking(Self::remove).await
+    }
+}
+
+/// Keeps the checkpoint in the configured `DataStorage` backend, keyed by
+/// indexer id so several indexers can share one backend.
+pub struct StorageCheckpointStore {
+    storage: Arc<StorageManager>,
+    key: String,
+}
+
+impl StorageCheckpointStore {
+    pub fn new(storage: Arc<StorageManager>, indexer_id: &str) -> Self {
+        Self {
+            storage,
+            key: format!("kanshi:checkpoint:{}", indexer_id),
+        }
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for StorageCheckpointStore {
+    async fn load(&self) -> Result<Option<Value>> {
+        self.storage
+            .retrieve::<Value>(&self.key)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to load checkpoint {}: {}", self.key, e))
+    }
+
+    async fn save(&self, state: &Value) -> Result<()> {
+        self.storage
+            .store(&self.key, state)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to save checkpoint {}: {}", self.key, e))
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.storage
+            .delete(&self.key)
+            .await
+            .map(|_| ())
+            .map_err(|e| anyhow::anyhow!("Failed to clear checkpoint {}: {}", self.key, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A store in an empty directory of its own, removed by the test.
+    fn store(name: &str) -> (PathBuf, FileCheckpointStore) {
+        let dir = std::env::temp_dir().join(format!("kanshi-checkpoint-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let store = FileCheckpointStore::new(dir.join("state.json"));
+        (dir, store)
+    }
+
+    #[tokio::test]
+    async fn the_previous_checkpoint_is_kept_as_a_backup() {
+        let (dir, store) = store("backup");
+        assert_eq!(store.load().await.unwrap(), None);
+
+        store.save(&json!({ "block": 1 })).await.unwrap();
+        assert!(!store.backup_path().exists());
+        store.save(&json!({ "block": 2 })).await.unwrap();
+
+        assert_eq!(store.load().await.unwrap(), Some(json!({ "block": 2 })));
+        let backup = fs::read_to_string(store.backup_path()).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&backup).unwrap(), json!({ "block": 1 }));
+        assert!(!store.sibling(".tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_truncated_or_corrupt_checkpoint_is_an_error() {
+        let (dir, store) = store("corrupt");
+        store.save(&json!({ "block": 1, "hash": "0x01" })).await.unwrap();
+
+        let state = fs::read_to_string(&store.path).unwrap();
+        fs::write(&store.path, &state[..state.len() / 2]).unwrap();
+        let error = store.load().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("state.json.bak"), "{:#}", error);
+
+        fs::write(&store.path, b"\x00\xffnot json").unwrap();
+        assert!(store.load().await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_stray_temporary_file_is_ignored() {
+        let (dir, store) = store("stray");
+        store.save(&json!({ "block": 1 })).await.unwrap();
+
+        // Left behind by a crash between the write and the rename
+        fs::write(store.sibling(".tmp"), "{ \"block\": ").unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(json!({ "block": 1 })));
+
+        store.save(&json!({ "block": 2 })).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(json!({ "block": 2 })));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
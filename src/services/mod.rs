@@ -1,2 +1,3 @@
 pub mod  redis;
-pub mod dataStore;
\ No newline at end of file
+pub mod dataStore;
+pub mod checkpoint;
\ No newline at end of file
@@ -0,0 +1,150 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::dna::journal::BlockJournal;
+use crate::dna::state_update::StateUpdateData;
+use crate::dna::EventData;
+use crate::services::dataStore::{StorageManager, TypedStorage};
+
+/// Where the records produced by the indexer are written. A write error
+/// stops the consumer before the block is acknowledged.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn write_event(&self, event: &EventData) -> Result<()>;
+
+    async fn write_state_update(&self, _update: &StateUpdateData) -> Result<()> {
+        Ok(())
+    }
+
+    /// Undoes what was written for the blocks after `block_number`, or for
+    /// every block when `None`.
+    async fn rollback(&self, _block_number: Option<u64>, _invalidated: &[EventData]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes everything written so far durable, called at the end of every
+    /// block before it is acknowledged and once more when the indexer stops.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Prints records, useful to try a configuration out.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write_event(&self, event: &EventData) -> Result<()> {
+        println!(
+            "🔥 Received {:?} Event from {} (block {}, tx {}): {:?}\n\n",
+            event.finality, event.contract, event.block_number, event.transaction_hash, event
+        );
+        Ok(())
+    }
+
+    async fn write_state_update(&self, update: &StateUpdateData) -> Result<()> {
+        println!(
+            "🧬 State update in block {}: {} storage diffs, {} deployments, {} nonce updates\n\n",
+            update.block_number,
+            update.storage_diffs.len(),
+            update.deployed_contracts.len(),
+            update.nonce_updates.len()
+        );
+        Ok(())
+    }
+}
+
+/// Writes records as JSON to the `StorageManager` backend, under
+/// `{key_prefix}:{block}:{event_index}` for events and
+/// `{key_prefix}:{block}:state` for state updates. Keys of recent blocks
+/// are remembered so they can be deleted on rollback, or once a newer
+/// version of a pending block no longer writes them.
+pub struct StorageSink {
+    storage: Arc<StorageManager>,
+    key_prefix: String,
+    keys: Mutex<WrittenKeys>,
+}
+
+#[derive(Default)]
+struct WrittenKeys {
+    journal: BlockJournal<String>,
+    /// Blocks written since the last flush.
+    open_blocks: BTreeSet<u64>,
+    /// Keys of the previous versions of the open blocks, deleted on flush
+    /// unless written again.
+    stale: Vec<String>,
+}
+
+impl StorageSink {
+    pub fn new(storage: Arc<StorageManager>, key_prefix: impl Into<String>) -> Self {
+        Self {
+            storage,
+            key_prefix: key_prefix.into(),
+            keys: Mutex::new(WrittenKeys::default()),
+        }
+    }
+
+    async fn write<T: Serialize + Send + Sync>(&self, block_number: u64, key: String, value: &T) -> Result<()> {
+        self.storage
+            .store(&key, value)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to store {}: {}", key, e))?;
+
+        let mut keys = self.keys.lock().unwrap();
+        // A block written again is a newer version of a pending block, what
+        // it doesn't write anymore is deleted on flush
+        if keys.open_blocks.insert(block_number) {
+            let previous = keys.journal.take(block_number);
+            keys.stale.extend(previous);
+        }
+        keys.journal.record(block_number, key);
+        keys.journal.prune(block_number);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for StorageSink {
+    async fn write_event(&self, event: &EventData) -> Result<()> {
+        let key = format!("{}:{}:{}", self.key_prefix, event.block_number, event.event_index);
+        self.write(event.block_number, key, event).await
+    }
+
+    async fn write_state_update(&self, update: &StateUpdateData) -> Result<()> {
+        let key = format!("{}:{}:state", self.key_prefix, update.block_number);
+        self.write(update.block_number, key, update).await
+    }
+
+    async fn rollback(&self, block_number: Option<u64>, _invalidated: &[EventData]) -> Result<()> {
+        let invalidated = self.keys.lock().unwrap().journal.rollback(block_number);
+
+        for key in &invalidated {
+            if let Err(e) = self.storage.delete(key).await {
+                println!("⚠️ [Warning] Failed to delete key {} on rollback: {:?}", key, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let stale: Vec<String> = {
+            let mut keys = self.keys.lock().unwrap();
+            let open_blocks = std::mem::take(&mut keys.open_blocks);
+            let stale = std::mem::take(&mut keys.stale);
+            let written: HashSet<&String> = open_blocks.iter().flat_map(|b| keys.journal.get(*b)).collect();
+            stale.into_iter().filter(|key| !written.contains(key)).collect()
+        };
+
+        for key in &stale {
+            self.storage
+                .delete(key)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to delete stale key {}: {}", key, e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::config::RetryConfig;
+
+/// Exponential backoff with random jitter, driven by a `RetryConfig`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: RetryConfig,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: RetryConfig) -> Self {
+        Self { config, attempt: 0 }
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` once
+    /// `max_retries` consecutive attempts have failed.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max_retries) = self.config.max_retries {
+            if self.attempt >= max_retries {
+                return None;
+            }
+        }
+
+        let jitter = if self.config.jitter > 0.0 {
+            rand::thread_rng().gen_range(0.0..=self.config.jitter)
+        } else {
+            0.0
+        };
+        let delay = self.delay(jitter);
+
+        self.attempt += 1;
+        Some(delay)
+    }
+
+    /// Delay of the current attempt for a given jitter sample, never more
+    /// than `max_delay_ms`.
+    fn delay(&self, jitter: f64) -> Duration {
+        let exponential = self.config.initial_delay_ms as f64
+            * self.config.multiplier.powi(self.attempt as i32);
+        let jittered = exponential * (1.0 + jitter);
+        Duration::from_millis(jittered.min(self.config.max_delay_ms as f64) as u64)
+    }
+
+    /// Called once the connection is healthy again.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Number of consecutive failed attempts.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_retries: Option<u32>) -> RetryConfig {
+        RetryConfig {
+            initial_delay_ms: 100,
+            max_delay_ms: 1_000,
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_retries,
+        }
+    }
+
+    #[test]
+    fn delays_grow_until_the_cap() {
+        let mut backoff = Backoff::new(config(None));
+        let delays: Vec<u64> = (0..6)
+            .map(|_| backoff.next_delay().unwrap().as_millis() as u64)
+            .collect();
+
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+    }
+
+    #[test]
+    fn jitter_never_exceeds_the_cap() {
+        let mut backoff = Backoff::new(RetryConfig { jitter: 0.5, ..config(None) });
+        assert_eq!(backoff.delay(0.5), Duration::from_millis(150));
+
+        backoff.attempt = 3;
+        assert_eq!(backoff.delay(0.5), Duration::from_millis(1_000));
+        for _ in 0..20 {
+            assert!(backoff.next_delay().unwrap() <= Duration::from_millis(1_000));
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_retries_until_reset() {
+        let mut backoff = Backoff::new(config(Some(2)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(200)));
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempt(), 2);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+    }
+}
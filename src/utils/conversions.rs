@@ -1,13 +1,120 @@
 use apibara_core::starknet::v1alpha2::FieldElement;
-use bigdecimal::num_bigint::BigInt;
+use bigdecimal::num_bigint::{BigInt, Sign};
 use bigdecimal::BigDecimal;
 use starknet::core::types::{Felt, U256};
 
-/// Converts an hexadecimal string with decimals to BigDecimal.
-pub fn hex_str_to_big_decimal(hex_price: &str, decimals: i64) -> BigDecimal {
-    let cleaned_hex = hex_price.trim_start_matches("0x");
-    let price_bigint = BigInt::parse_bytes(cleaned_hex.as_bytes(), 16).unwrap();
-    BigDecimal::new(price_bigint, decimals)
+/// Largest power of ten applied when scaling a `BigDecimal`, `10^80` is
+/// already above `2^256`.
+const MAX_DECIMAL_SHIFT: i64 = 80;
+
+/// Why a value could not be converted between hex strings, `Felt`, `U256`
+/// and `BigDecimal`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    InvalidHex(String),
+    /// Felts and `u256` are unsigned.
+    Negative(BigDecimal),
+    /// The value has more decimal places than `decimals`.
+    Inexact { value: BigDecimal, decimals: i64 },
+    /// The scaled value does not fit in `ty`.
+    Overflow { value: BigDecimal, ty: &'static str },
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::InvalidHex(hex) => write!(f, "invalid hex string {:?}", hex),
+            ConversionError::Negative(value) => write!(f, "{} is negative", value),
+            ConversionError::Inexact { value, decimals } => {
+                write!(f, "{} has more than {} decimal places", value, decimals)
+            }
+            ConversionError::Overflow { value, ty } => write!(f, "{} does not fit in {}", value, ty),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Parses a hex string, with or without `0x` prefix, holding an amount with
+/// `decimals` decimal places: `0x2710` with 2 decimals is `100.00`.
+pub fn hex_str_to_big_decimal(hex: &str, decimals: i64) -> Result<BigDecimal, ConversionError> {
+    let cleaned_hex = hex.trim_start_matches("0x");
+    // `parse_bytes` would also take a sign and `_` separators
+    if cleaned_hex.is_empty() || !cleaned_hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ConversionError::InvalidHex(hex.to_string()));
+    }
+    let amount = BigInt::parse_bytes(cleaned_hex.as_bytes(), 16)
+        .ok_or_else(|| ConversionError::InvalidHex(hex.to_string()))?;
+    Ok(BigDecimal::new(amount, decimals))
+}
+
+/// Parses a hex string, with or without `0x` prefix, into a `Felt`.
+pub fn hex_str_to_felt(hex: &str) -> Result<Felt, ConversionError> {
+    let prefixed = format!("0x{}", hex.trim_start_matches("0x"));
+    Felt::from_hex(&prefixed).map_err(|_| ConversionError::InvalidHex(hex.to_string()))
+}
+
+/// Reads `value` as an amount with `decimals` decimal places.
+pub fn felt_to_big_decimal(value: &Felt, decimals: i64) -> BigDecimal {
+    BigDecimal::new(BigInt::from_bytes_be(Sign::Plus, &value.to_bytes_be()), decimals)
+}
+
+/// Reads `value` as an amount with `decimals` decimal places.
+pub fn u256_to_big_decimal(value: &U256, decimals: i64) -> BigDecimal {
+    let mut bytes = value.high().to_be_bytes().to_vec();
+    bytes.extend_from_slice(&value.low().to_be_bytes());
+    BigDecimal::new(BigInt::from_bytes_be(Sign::Plus, &bytes), decimals)
+}
+
+/// The integer `value * 10^decimals`, failing when it is negative or has a
+/// fractional part.
+fn scaled_integer(value: &BigDecimal, decimals: i64, ty: &'static str) -> Result<BigInt, ConversionError> {
+    let (digits, scale) = value.normalized().as_bigint_and_exponent();
+    if digits.sign() == Sign::Minus {
+        return Err(ConversionError::Negative(value.clone()));
+    }
+    if digits.sign() == Sign::NoSign {
+        return Ok(digits);
+    }
+
+    // Normalized digits have no trailing zero, dividing them always leaves a remainder
+    let shift = decimals.saturating_sub(scale);
+    if shift < 0 {
+        return Err(ConversionError::Inexact { value: value.clone(), decimals });
+    }
+    if shift > MAX_DECIMAL_SHIFT {
+        return Err(ConversionError::Overflow { value: value.clone(), ty });
+    }
+    Ok(digits * BigInt::from(10u8).pow(shift as u32))
+}
+
+/// Converts an amount to its integer representation with `decimals`
+/// decimal places: `1.5` with 18 decimals is `1500000000000000000`.
+pub fn big_decimal_to_u256(value: &BigDecimal, decimals: i64) -> Result<U256, ConversionError> {
+    let amount = scaled_integer(value, decimals, "u256")?;
+    let (_, bytes) = amount.to_bytes_be();
+    if bytes.len() > 32 {
+        return Err(ConversionError::Overflow { value: value.clone(), ty: "u256" });
+    }
+
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    let (mut high, mut low) = ([0u8; 16], [0u8; 16]);
+    high.copy_from_slice(&padded[..16]);
+    low.copy_from_slice(&padded[16..]);
+    Ok(U256::from_words(u128::from_be_bytes(low), u128::from_be_bytes(high)))
+}
+
+/// Converts an amount to its integer representation with `decimals`
+/// decimal places, failing when it is not below the field prime.
+pub fn big_decimal_to_felt(value: &BigDecimal, decimals: i64) -> Result<Felt, ConversionError> {
+    let amount = scaled_integer(value, decimals, "felt252")?;
+    // 2^251 + 17 * 2^192 + 1
+    let prime = (BigInt::from(1u8) << 251) + (BigInt::from(17u8) << 192) + BigInt::from(1u8);
+    if amount >= prime {
+        return Err(ConversionError::Overflow { value: value.clone(), ty: "felt252" });
+    }
+    Ok(Felt::from(amount))
 }
 
 /// Converts a Felt element from starknet-rs to a FieldElement from Apibara-core.
@@ -20,14 +127,31 @@ pub fn apibara_field_as_felt(value: &FieldElement) -> Felt {
     Felt::from_bytes_be(&value.to_bytes())
 }
 
-/// Converts a BigDecimal to a U256.
-pub fn big_decimal_to_u256(value: BigDecimal) -> U256 {
-    U256::from(big_decimal_to_felt(value))
+/// Encodes raw bytes as a `0x` prefixed hex string.
+pub fn bytes_to_hex_string(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", hex)
 }
 
-pub fn big_decimal_to_felt(value: BigDecimal) -> Felt {
-    let (amount, _): (BigInt, _) = value.as_bigint_and_exponent();
-    Felt::from(amount.clone())
+/// Encodes a `Felt` like `field_to_hex_string`, zero padded to 64 digits.
+pub fn felt_to_hex_string(value: &Felt) -> String {
+    bytes_to_hex_string(&value.to_bytes_be())
+}
+
+/// Decodes a hex string, with or without `0x` prefix, into raw bytes.
+pub fn hex_string_to_bytes(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let hex_clean = hex.trim_start_matches("0x");
+    if !hex_clean.is_ascii() || hex_clean.len() % 2 != 0 {
+        anyhow::bail!("Invalid hex string {:?}", hex);
+    }
+
+    (0..hex_clean.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex_clean[i..i + 2], 16)
+                .map_err(|_| anyhow::anyhow!("Invalid hex string {:?}", hex))
+        })
+        .collect()
 }
 
 // Helper function to convert FieldElement to hex string
@@ -40,6 +164,11 @@ pub fn field_to_hex_string(field: &apibara_core::starknet::v1alpha2::FieldElemen
     )
 }
 
+/// Hex string of an optional `FieldElement`, empty when it is missing.
+pub fn optional_field_to_hex_string(field: Option<&apibara_core::starknet::v1alpha2::FieldElement>) -> String {
+    field.map(field_to_hex_string).unwrap_or_default()
+}
+
 // Helper function to convert hex string to UTF-8 string
 fn hex_to_string(hex: &str) -> String {
     // Remove 0x prefix if present
@@ -75,4 +204,435 @@ pub fn field_to_string(field: &apibara_core::starknet::v1alpha2::FieldElement) -
     } else {
         result
     }
-}
\ No newline at end of file
+}
+
+/// Why a Cairo value could not be decoded from its felts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The felts ran out before the value was complete.
+    UnexpectedEnd,
+    /// The felt does not fit in the requested integer type.
+    OutOfRange { ty: &'static str, value: Felt },
+    InvalidBool(Felt),
+    /// The felt is not an ASCII short string.
+    InvalidShortString(Felt),
+    InvalidByteArray(String),
+    /// The variant index is not one of the `variants` of the enum.
+    InvalidVariant { index: Felt, variants: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::OutOfRange { ty, value } => {
+                write!(f, "{} does not fit in {}", value.to_hex_string(), ty)
+            }
+            DecodeError::InvalidBool(value) => write!(f, "{} is not a bool", value.to_hex_string()),
+            DecodeError::InvalidShortString(value) => {
+                write!(f, "{} is not a short string", value.to_hex_string())
+            }
+            DecodeError::InvalidByteArray(reason) => write!(f, "invalid ByteArray: {}", reason),
+            DecodeError::InvalidVariant { index, variants } => write!(
+                f,
+                "variant {} out of the {} variants of the enum",
+                index.to_hex_string(),
+                variants
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Next felt of a serialized Cairo value.
+pub fn decode_felt<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I) -> Result<Felt, DecodeError> {
+    felts.next().ok_or(DecodeError::UnexpectedEnd)
+}
+
+fn felt_to_u128(value: Felt) -> Option<u128> {
+    let bytes = value.to_bytes_be();
+    match bytes[..16].iter().all(|b| *b == 0) {
+        true => Some(u128::from_be_bytes(bytes[16..].try_into().ok()?)),
+        false => None,
+    }
+}
+
+/// Unsigned integer (`u8` to `u128`, `usize`), e.g. `decode_unsigned::<u32>`.
+pub fn decode_unsigned<T, I>(felts: &mut I) -> Result<T, DecodeError>
+where
+    T: TryFrom<u128>,
+    I: Iterator<Item = Felt> + ?Sized,
+{
+    let value = decode_felt(felts)?;
+    felt_to_u128(value)
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or(DecodeError::OutOfRange { ty: std::any::type_name::<T>(), value })
+}
+
+/// Signed integer (`i8` to `i128`), negative values being encoded as
+/// `P - |value|` in the field.
+pub fn decode_signed<T, I>(felts: &mut I) -> Result<T, DecodeError>
+where
+    T: TryFrom<i128>,
+    I: Iterator<Item = Felt> + ?Sized,
+{
+    let value = decode_felt(felts)?;
+    let signed = match felt_to_u128(value) {
+        Some(positive) => i128::try_from(positive).ok(),
+        None => felt_to_u128(-value)
+            .filter(|magnitude| *magnitude <= i128::MIN.unsigned_abs())
+            .map(|magnitude| (magnitude as i128).wrapping_neg()),
+    };
+
+    signed
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or(DecodeError::OutOfRange { ty: std::any::type_name::<T>(), value })
+}
+
+/// `u256`, serialized as its low then high 128 bits.
+pub fn decode_u256<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I) -> Result<U256, DecodeError> {
+    let low = decode_unsigned::<u128, _>(felts)?;
+    let high = decode_unsigned::<u128, _>(felts)?;
+    Ok(U256::from_words(low, high))
+}
+
+pub fn decode_bool<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I) -> Result<bool, DecodeError> {
+    match decode_felt(felts)? {
+        value if value == Felt::ZERO => Ok(false),
+        value if value == Felt::ONE => Ok(true),
+        value => Err(DecodeError::InvalidBool(value)),
+    }
+}
+
+/// Cairo short string: up to 31 ASCII characters packed in a felt.
+pub fn decode_short_string<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I) -> Result<String, DecodeError> {
+    let value = decode_felt(felts)?;
+    let bytes = value.to_bytes_be();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+
+    match bytes[0] == 0 && bytes[start..].is_ascii() {
+        true => Ok(String::from_utf8_lossy(&bytes[start..]).into_owned()),
+        false => Err(DecodeError::InvalidShortString(value)),
+    }
+}
+
+/// `ByteArray`: the number of full 31 bytes words, the words, then the
+/// pending word and its length in bytes.
+pub fn decode_byte_array<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I) -> Result<String, DecodeError> {
+    let words = decode_unsigned::<u32, _>(felts)?;
+    let mut bytes = Vec::new();
+    for _ in 0..words {
+        let word = decode_felt(felts)?.to_bytes_be();
+        if word[0] != 0 {
+            return Err(DecodeError::InvalidByteArray("word longer than 31 bytes".to_string()));
+        }
+        bytes.extend_from_slice(&word[1..]);
+    }
+
+    let pending_word = decode_felt(felts)?.to_bytes_be();
+    let pending_len = decode_unsigned::<usize, _>(felts)?;
+    if pending_len > 30 {
+        return Err(DecodeError::InvalidByteArray(format!("pending word of {} bytes", pending_len)));
+    }
+    let (padding, pending) = pending_word.split_at(32 - pending_len);
+    if padding.iter().any(|b| *b != 0) {
+        return Err(DecodeError::InvalidByteArray(format!(
+            "pending word longer than {} bytes",
+            pending_len
+        )));
+    }
+    bytes.extend_from_slice(pending);
+
+    String::from_utf8(bytes).map_err(|_| DecodeError::InvalidByteArray("not UTF-8".to_string()))
+}
+
+/// `Array<T>` or `Span<T>`: the length, then each item decoded with
+/// `decode_item`.
+pub fn decode_array<I, T, E, F>(felts: &mut I, mut decode_item: F) -> Result<Vec<T>, E>
+where
+    I: Iterator<Item = Felt> + ?Sized,
+    E: From<DecodeError>,
+    F: FnMut(&mut I) -> Result<T, E>,
+{
+    let len = decode_unsigned::<u32, _>(felts)?;
+    (0..len).map(|_| decode_item(felts)).collect()
+}
+
+/// Index of the variant of an enum with `variants` variants.
+pub fn decode_variant_index<I: Iterator<Item = Felt> + ?Sized>(felts: &mut I, variants: usize) -> Result<usize, DecodeError> {
+    let index = decode_felt(felts)?;
+    felt_to_u128(index)
+        .and_then(|i| usize::try_from(i).ok())
+        .filter(|i| *i < variants)
+        .ok_or(DecodeError::InvalidVariant { index, variants })
+}
+
+/// `Option<T>`, variant `0` being `Some` followed by the value and `1`
+/// being `None`.
+pub fn decode_option<I, T, E, F>(felts: &mut I, decode_some: F) -> Result<Option<T>, E>
+where
+    I: Iterator<Item = Felt> + ?Sized,
+    E: From<DecodeError>,
+    F: FnOnce(&mut I) -> Result<T, E>,
+{
+    match decode_variant_index(felts, 2)? {
+        0 => decode_some(felts).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decimal(value: &str) -> BigDecimal {
+        value.parse().unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn hex_amounts_round_trip_to_u256(low in any::<u128>(), high in any::<u128>(), decimals in 0i64..=40) {
+            let hex = format!("0x{:x}{:032x}", high, low);
+            let amount = hex_str_to_big_decimal(&hex, decimals).unwrap();
+            prop_assert_eq!(big_decimal_to_u256(&amount, decimals), Ok(U256::from_words(low, high)));
+        }
+
+        #[test]
+        fn hex_amounts_round_trip_to_felt(bytes in any::<[u8; 32]>(), decimals in 0i64..=40) {
+            let felt = Felt::from_bytes_be(&bytes);
+            let amount = hex_str_to_big_decimal(&felt_to_hex_string(&felt), decimals).unwrap();
+            prop_assert_eq!(big_decimal_to_felt(&amount, decimals), Ok(felt));
+        }
+
+        #[test]
+        fn u256_round_trips_through_big_decimal(low in any::<u128>(), high in any::<u128>(), decimals in 0i64..=40) {
+            let value = U256::from_words(low, high);
+            prop_assert_eq!(big_decimal_to_u256(&u256_to_big_decimal(&value, decimals), decimals), Ok(value));
+        }
+
+        #[test]
+        fn big_decimal_round_trips_through_u256(digits in any::<u128>(), scale in 0i64..=20, extra in 0i64..=20) {
+            let amount = BigDecimal::new(BigInt::from(digits), scale);
+            let decimals = scale + extra;
+            let value = big_decimal_to_u256(&amount, decimals).unwrap();
+            prop_assert_eq!(u256_to_big_decimal(&value, decimals), amount);
+        }
+
+        #[test]
+        fn felt_round_trips_through_big_decimal(bytes in any::<[u8; 32]>(), decimals in 0i64..=40) {
+            let felt = Felt::from_bytes_be(&bytes);
+            prop_assert_eq!(big_decimal_to_felt(&felt_to_big_decimal(&felt, decimals), decimals), Ok(felt));
+        }
+
+        #[test]
+        fn big_decimal_round_trips_through_felt(digits in any::<u128>(), scale in 0i64..=20, extra in 0i64..=20) {
+            let amount = BigDecimal::new(BigInt::from(digits), scale);
+            let decimals = scale + extra;
+            let felt = big_decimal_to_felt(&amount, decimals).unwrap();
+            prop_assert_eq!(felt_to_big_decimal(&felt, decimals), amount);
+        }
+    }
+
+    #[test]
+    fn malformed_hex_is_rejected() {
+        for hex in ["", "0x", "-1", "0x_1", "0x1g", "+1"] {
+            assert_eq!(
+                hex_str_to_big_decimal(hex, 0),
+                Err(ConversionError::InvalidHex(hex.to_string())),
+                "{:?}",
+                hex
+            );
+        }
+    }
+
+    #[test]
+    fn amounts_that_do_not_fit_are_rejected() {
+        let prime = decimal("3618502788666131213697322783095070105623107215331596699973092056135872020481");
+        let cases = [
+            (decimal("1.5"), 0, Err(ConversionError::Inexact { value: decimal("1.5"), decimals: 0 })),
+            (decimal("1.5"), 1, Ok(Felt::from(15u8))),
+            (decimal("-1"), 0, Err(ConversionError::Negative(decimal("-1")))),
+            (decimal("0"), 100, Ok(Felt::ZERO)),
+            (prime.clone(), 0, Err(ConversionError::Overflow { value: prime.clone(), ty: "felt252" })),
+            (
+                decimal("3618502788666131213697322783095070105623107215331596699973092056135872020480"),
+                0,
+                Ok(Felt::MAX),
+            ),
+            (decimal("1"), MAX_DECIMAL_SHIFT + 1, Err(ConversionError::Overflow { value: decimal("1"), ty: "felt252" })),
+        ];
+        for (value, decimals, expected) in cases {
+            assert_eq!(big_decimal_to_felt(&value, decimals), expected, "{} with {} decimals", value, decimals);
+        }
+
+        let two_pow_256 = BigDecimal::from(BigInt::from(1u8) << 256);
+        let cases = [
+            (decimal("1.5"), 0, Err(ConversionError::Inexact { value: decimal("1.5"), decimals: 0 })),
+            (decimal("-0.5"), 1, Err(ConversionError::Negative(decimal("-0.5")))),
+            (two_pow_256.clone(), 0, Err(ConversionError::Overflow { value: two_pow_256.clone(), ty: "u256" })),
+            (decimal("1"), MAX_DECIMAL_SHIFT + 1, Err(ConversionError::Overflow { value: decimal("1"), ty: "u256" })),
+        ];
+        for (value, decimals, expected) in cases {
+            assert_eq!(big_decimal_to_u256(&value, decimals), expected, "{} with {} decimals", value, decimals);
+        }
+    }
+
+    #[test]
+    fn decode_signed_reads_negative_values_from_the_top_of_the_field() {
+        let i8_cases = [
+            (Felt::from(127u8), Some(i8::MAX)),
+            (Felt::from(128u8), None),
+            (-Felt::from(128u8), Some(i8::MIN)),
+            (-Felt::from(129u8), None),
+            (Felt::MAX, Some(-1)),
+        ];
+        for (felt, expected) in i8_cases {
+            assert_eq!(decode_signed::<i8, _>(&mut std::iter::once(felt)).ok(), expected, "{:?}", felt);
+        }
+
+        let i128_cases = [
+            (Felt::from(i128::MAX as u128), Some(i128::MAX)),
+            (Felt::from(1u128 << 127), None),
+            (-Felt::from(i128::MIN.unsigned_abs()), Some(i128::MIN)),
+            (-Felt::from(i128::MIN.unsigned_abs() + 1), None),
+            (Felt::MAX, Some(-1)),
+        ];
+        for (felt, expected) in i128_cases {
+            assert_eq!(decode_signed::<i128, _>(&mut std::iter::once(felt)).ok(), expected, "{:?}", felt);
+        }
+    }
+
+    #[test]
+    fn decode_u256_reads_the_low_word_first() {
+        let cases = [
+            (vec![Felt::from(1u8), Felt::from(2u8)], Ok(U256::from_words(1, 2))),
+            (vec![Felt::from(1u8)], Err(DecodeError::UnexpectedEnd)),
+            (vec![], Err(DecodeError::UnexpectedEnd)),
+        ];
+        for (felts, expected) in cases {
+            assert_eq!(decode_u256(&mut felts.clone().into_iter()), expected, "{:?}", felts);
+        }
+
+        let too_wide = Felt::from(u128::MAX) + Felt::ONE;
+        assert!(matches!(
+            decode_u256(&mut [too_wide, Felt::ZERO].into_iter()),
+            Err(DecodeError::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn decode_bool_only_accepts_zero_and_one() {
+        let cases = [
+            (vec![Felt::ZERO], Ok(false)),
+            (vec![Felt::ONE], Ok(true)),
+            (vec![Felt::from(2u8)], Err(DecodeError::InvalidBool(Felt::from(2u8)))),
+            (vec![Felt::MAX], Err(DecodeError::InvalidBool(Felt::MAX))),
+            (vec![], Err(DecodeError::UnexpectedEnd)),
+        ];
+        for (felts, expected) in cases {
+            assert_eq!(decode_bool(&mut felts.clone().into_iter()), expected, "{:?}", felts);
+        }
+    }
+
+    #[test]
+    fn decode_short_string_only_accepts_ascii() {
+        let word = Felt::from_bytes_be_slice;
+        let cases = [
+            (vec![word(b"hello")], Ok("hello".to_string())),
+            (vec![Felt::ZERO], Ok(String::new())),
+            (vec![word(&[b'a'; 31])], Ok("a".repeat(31))),
+            (vec![word("héllo".as_bytes())], Err(DecodeError::InvalidShortString(word("héllo".as_bytes())))),
+            (vec![word(&[0x80])], Err(DecodeError::InvalidShortString(word(&[0x80])))),
+            (vec![Felt::MAX], Err(DecodeError::InvalidShortString(Felt::MAX))),
+            (vec![], Err(DecodeError::UnexpectedEnd)),
+        ];
+        for (felts, expected) in cases {
+            assert_eq!(decode_short_string(&mut felts.clone().into_iter()), expected, "{:?}", felts);
+        }
+    }
+
+    #[test]
+    fn decode_array_reads_its_length_first() {
+        let decode = |felts: Vec<Felt>| {
+            decode_array::<_, _, DecodeError, _>(&mut felts.into_iter(), |felts| decode_unsigned::<u8, _>(felts))
+        };
+
+        assert_eq!(decode(vec![Felt::ZERO]), Ok(vec![]));
+        assert_eq!(decode(vec![Felt::from(2u8), Felt::from(7u8), Felt::from(9u8)]), Ok(vec![7, 9]));
+        // A length longer than the data, or no length at all
+        assert_eq!(decode(vec![Felt::from(3u8), Felt::from(7u8)]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(decode(vec![]), Err(DecodeError::UnexpectedEnd));
+        // Items are checked like any other value
+        assert!(matches!(
+            decode(vec![Felt::ONE, Felt::from(256u16)]),
+            Err(DecodeError::OutOfRange { .. })
+        ));
+        assert!(matches!(decode(vec![Felt::MAX]), Err(DecodeError::OutOfRange { .. })));
+    }
+
+    #[test]
+    fn decode_byte_array_checks_the_pending_word() {
+        let word = Felt::from_bytes_be_slice;
+        let len = |n: u64| Felt::from(n);
+        // `None` expects an `InvalidByteArray` error
+        let cases: [(&str, Vec<Felt>, Option<&str>); 6] = [
+            ("empty", vec![len(0), Felt::ZERO, len(0)], Some("")),
+            ("pending only", vec![len(0), word(b"hello"), len(5)], Some("hello")),
+            (
+                "full word",
+                vec![len(1), word(&[b'a'; 31]), word(b"b"), len(1)],
+                Some("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab"),
+            ),
+            ("31 bytes pending word", vec![len(0), word(&[b'a'; 31]), len(31)], None),
+            ("nonzero padding", vec![len(0), word(b"xhello"), len(5)], None),
+            ("invalid UTF-8", vec![len(0), word(&[0xff, 0xfe]), len(2)], None),
+        ];
+        for (name, felts, expected) in cases {
+            let decoded = decode_byte_array(&mut felts.into_iter());
+            match expected {
+                Some(expected) => assert_eq!(decoded.as_deref(), Ok(expected), "{}", name),
+                None => assert!(
+                    matches!(decoded, Err(DecodeError::InvalidByteArray(_))),
+                    "{}: {:?}",
+                    name,
+                    decoded
+                ),
+            }
+        }
+
+        assert_eq!(
+            decode_byte_array(&mut [len(0), word(b"hello")].into_iter()),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn decode_variant_index_rejects_unknown_variants() {
+        let cases = [
+            (Felt::ZERO, Ok(0)),
+            (Felt::from(2u8), Ok(2)),
+            (Felt::from(3u8), Err(DecodeError::InvalidVariant { index: Felt::from(3u8), variants: 3 })),
+            (Felt::MAX, Err(DecodeError::InvalidVariant { index: Felt::MAX, variants: 3 })),
+        ];
+        for (index, expected) in cases {
+            assert_eq!(decode_variant_index(&mut std::iter::once(index), 3), expected, "{:?}", index);
+        }
+    }
+
+    #[test]
+    fn decode_option_reads_some_then_none() {
+        let decode = |felts: Vec<Felt>| {
+            decode_option::<_, _, DecodeError, _>(&mut felts.into_iter(), |felts| decode_unsigned::<u32, _>(felts))
+        };
+
+        assert_eq!(decode(vec![Felt::ZERO, Felt::from(7u8)]), Ok(Some(7)));
+        assert_eq!(decode(vec![Felt::ONE]), Ok(None));
+        assert_eq!(decode(vec![Felt::ZERO]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(
+            decode(vec![Felt::from(2u8)]),
+            Err(DecodeError::InvalidVariant { index: Felt::from(2u8), variants: 2 })
+        );
+    }
+}
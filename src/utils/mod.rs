@@ -1 +1,2 @@
-pub mod conversions;
\ No newline at end of file
+pub mod backoff;
+pub mod conversions;
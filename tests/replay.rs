@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use kanshi::config::{Config, ContractConfig, Finality, SourceKind};
+use kanshi::dna::state::BlockState;
+use kanshi::dna::{IndexerMessage, IndexerService};
+use starknet::core::types::Felt;
+use tokio::sync::mpsc;
+
+/// Blocks 100 to 102 of the `vault` contract, block 101 being reorganized
+/// once streamed.
+const RECORDING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/recording.jsonl");
+
+fn checkpoint_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("kanshi-replay-{}-{}.json", std::process::id(), name));
+    remove_checkpoint(&path);
+    path
+}
+
+fn remove_checkpoint(path: &Path) {
+    for suffix in ["", ".bak", ".tmp"] {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(suffix);
+        let _ = std::fs::remove_file(name);
+    }
+}
+
+fn config(checkpoint: &Path) -> Config {
+    let mut config = Config {
+        contracts: vec![ContractConfig {
+            name: "vault".to_string(),
+            address: Felt::from_hex("0x0123").unwrap(),
+            events: Vec::new(),
+            abi_path: None,
+            abi: None,
+        }],
+        starting_block: 100,
+        ending_block: Some(102),
+        persist_backfill_state: true,
+        write_path: checkpoint.display().to_string(),
+        finality: Finality::Accepted,
+        ..Config::default()
+    };
+    config.source.kind = SourceKind::Replay;
+    config.source.replay_path = Some(PathBuf::from(RECORDING));
+    config
+}
+
+fn checkpointed_block(path: &Path) -> Option<u64> {
+    let state = std::fs::read_to_string(path).ok()?;
+    Some(serde_json::from_str::<BlockState>(&state).unwrap().cursor.order_key)
+}
+
+fn describe(message: &IndexerMessage) -> String {
+    match message {
+        IndexerMessage::Event(event) => format!(
+            "event {} #{} of {} {}",
+            event.block_number, event.event_index, event.contract, event.data[0]
+        ),
+        IndexerMessage::BlockEnd(block) => format!("end {}", block.block_number),
+        IndexerMessage::Rollback { block_number, invalidated } => {
+            format!("rollback to {:?}, {} invalidated", block_number, invalidated.len())
+        }
+        IndexerMessage::BackfillComplete(summary) => {
+            format!("complete {}..={}", summary.starting_block, summary.ending_block)
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// Runs the indexer over the recording, acknowledging the blocks for which
+/// `ack` returns `true` and stopping the consumer after `stop_after` blocks
+/// ends, if set. Returns the messages received.
+async fn run(config: Config, ack: impl Fn(u64) -> bool, stop_after: Option<u64>) -> Vec<String> {
+    let mut service = IndexerService::new(config).await.unwrap();
+    let ack_handle = service.ack_handle();
+    let (tx, mut rx) = mpsc::channel(16);
+    let indexer = tokio::spawn(async move { service.run_forever_simplified(&tx).await });
+
+    let mut received = Vec::new();
+    let consume = async {
+        while let Some(message) = rx.recv().await {
+            received.push(describe(&message));
+            if let IndexerMessage::BlockEnd(block) = &message {
+                if ack(block.block_number) {
+                    ack_handle.ack(block);
+                }
+                if stop_after == Some(block.block_number) {
+                    break;
+                }
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), consume)
+        .await
+        .expect("the indexer stalled");
+    drop(rx);
+
+    tokio::time::timeout(Duration::from_secs(10), indexer)
+        .await
+        .expect("the indexer did not stop")
+        .unwrap()
+        .unwrap();
+    received
+}
+
+#[tokio::test]
+async fn replays_a_recording_through_the_pipeline() {
+    let checkpoint = checkpoint_path("pipeline");
+
+    let received = run(config(&checkpoint), |_| true, None).await;
+
+    assert_eq!(
+        received,
+        vec![
+            "event 100 #0 of vault 0x01",
+            "end 100",
+            "event 101 #0 of vault 0x02",
+            "event 101 #2 of vault 0x03",
+            "end 101",
+            "rollback to Some(100), 2 invalidated",
+            "event 101 #1 of vault 0x04",
+            "end 101",
+            "event 102 #0 of vault 0x05",
+            "end 102",
+            "complete 100..=102",
+        ]
+    );
+    assert_eq!(checkpointed_block(&checkpoint), Some(102));
+
+    remove_checkpoint(&checkpoint);
+}
+
+#[tokio::test]
+async fn checkpoint_only_advances_to_acknowledged_blocks() {
+    let checkpoint = checkpoint_path("acks");
+
+    // Block 102 is received but never acknowledged
+    let received = run(config(&checkpoint), |block| block < 102, Some(102)).await;
+    assert_eq!(received.last().map(String::as_str), Some("end 102"));
+    assert_eq!(checkpointed_block(&checkpoint), Some(101));
+
+    // The next run resumes after the acknowledged block
+    let received = run(config(&checkpoint), |_| true, None).await;
+    assert_eq!(
+        received,
+        vec!["event 102 #0 of vault 0x05", "end 102", "complete 100..=102"]
+    );
+    assert_eq!(checkpointed_block(&checkpoint), Some(102));
+
+    remove_checkpoint(&checkpoint);
+}